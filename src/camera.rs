@@ -34,6 +34,7 @@ impl Default for CameraController {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn camera_control_system(
     time: Res<Time<Real>>,
     mut windows: Query<(&Window, &mut CursorOptions)>,
@@ -1,3 +1,4 @@
 pub mod camera;
 pub mod md2;
+pub mod pak;
 pub mod pcx;
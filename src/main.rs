@@ -1,43 +1,122 @@
 use bevy::{camera::visibility::RenderLayers, prelude::*};
 use bevy_egui::{EguiContexts, EguiPlugin, EguiPrimaryContextPass, PrimaryEguiContext, egui};
 use md2_bevy::camera::{CameraController, camera_control_system};
-use md2_bevy::md2::{MD2Component, MD2Resource, spawn_md2};
+use md2_bevy::md2::gltf::{GltfOptions, GltfSkin, export_gltf, skin_png};
+use md2_bevy::md2::material::{Md2Material, PALETTE_RAMPS};
+use md2_bevy::md2::{
+    LoopMode, MD2Component, MD2Resource, Md2Asset, Md2Model, Md2Plugin, Md2SkinMaterials,
+    Md2Weapon, spawn_md2,
+};
+use md2_bevy::pak::PakAssetPlugin;
 use md2_bevy::pcx::PcxLoaderPlugin;
-use std::path::Path;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+const EXPORT_USAGE: &str = "usage: md2-bevy export <model.md2> <out.gltf|out.glb> [--skin <image>] [--reference] [--fps <fps>]";
+
+const COMPARE_USAGE: &str = "usage: md2-bevy compare <model.md2>";
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.get(1).map(String::as_str) {
+        Some("export") => Some(export(&args[2..])),
+        Some("compare") => Some(compare(&args[2..])),
+        _ => None,
+    };
+    if let Some(result) = result {
+        if let Err(e) = result {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     App::new()
+        // asset sources must be registered before the AssetPlugin
+        .add_plugins(PakAssetPlugin::default())
         .add_plugins(DefaultPlugins)
         .add_plugins(PcxLoaderPlugin)
+        .add_plugins(Md2Plugin)
         .add_plugins(EguiPlugin::default())
         .insert_resource(MD2Resource::load(Path::new("assets")))
         .add_systems(Startup, setup)
-        .add_systems(
-            Update,
-            (
-                camera_control_system,
-                keyboard_input_system,
-                animation_system,
-            ),
-        )
+        .add_systems(Update, (camera_control_system, keyboard_input_system))
         .add_systems(EguiPrimaryContextPass, ui_system)
         .run();
 }
 
-fn setup(
-    mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    md2s: Res<MD2Resource>,
-) {
-    spawn_md2(
-        md2s.curr_path(),
-        &mut commands,
-        &asset_server,
-        &mut materials,
-        &mut meshes,
-    );
+/// Export an MD2 model to glTF
+///
+/// The skin is embedded unless `--reference` is given, in which case it is
+/// written as a .png next to the output. Without `--skin` the model's first
+/// skin is looked up in its directory.
+fn export(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut paths = Vec::new();
+    let mut skin = None;
+    let mut reference = false;
+    let mut options = GltfOptions::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--skin" => skin = Some(PathBuf::from(args.next().ok_or(EXPORT_USAGE)?)),
+            "--reference" => reference = true,
+            "--fps" => options.fps = args.next().ok_or(EXPORT_USAGE)?.parse()?,
+            _ => paths.push(PathBuf::from(arg)),
+        }
+    }
+    let [model_path, out_path] = paths.as_slice() else {
+        return Err(EXPORT_USAGE.into());
+    };
+
+    let md2 = Md2Asset::from_bytes(&std::fs::read(model_path)?)?;
+
+    let skin = skin.or_else(|| {
+        let name = md2.skins().first()?.path.path().file_name()?.to_owned();
+        let path = model_path.with_file_name(name);
+        ["pcx", "png"]
+            .into_iter()
+            .map(|ext| path.with_extension(ext))
+            .find(|path| path.exists())
+    });
+
+    if let Some(skin) = skin {
+        let png = skin_png(&skin)?;
+        options.skin = if reference {
+            let png_path = out_path.with_extension("png");
+            std::fs::write(&png_path, png)?;
+            GltfSkin::Uri(png_path.file_name().unwrap().to_string_lossy().into_owned())
+        } else {
+            GltfSkin::Png(png)
+        };
+    }
+
+    export_gltf(&md2, &options).write(out_path)?;
+    Ok(())
+}
+
+/// Compare the mesh built from the GL commands against the triangle list
+fn compare(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let [model_path] = args else {
+        return Err(COMPARE_USAGE.into());
+    };
+
+    let mut md2 = Md2Asset::from_bytes(&std::fs::read(model_path)?)?;
+    let triangles = md2.layout().clone();
+    md2.use_gl_commands()?;
+
+    println!("triangle list / GL commands");
+    println!("{}", triangles.compare(md2.layout()));
+    Ok(())
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, md2s: Res<MD2Resource>) {
+    if let Some(path) = md2s.curr_path() {
+        let entity = spawn_md2(asset_server.load(path), Vec3::ZERO, &mut commands);
+        commands.entity(entity).insert(Selected);
+    } else {
+        warn!("No MD2 models found in assets");
+    }
 
     // Transform for the camera and lighting, looking at (0,0,0) (the position of the mesh).
     let camera_transform = Transform::from_xyz(0.0, 0.0, 3.0).looking_at(
@@ -56,6 +135,20 @@ fn setup(
         CameraController::default(),
     ));
 
+    // Key light from above and in front of the model, the ambient light
+    // keeps the unlit side from going completely black.
+    commands.spawn((
+        DirectionalLight {
+            illuminance: light_consts::lux::OVERCAST_DAY,
+            ..default()
+        },
+        Transform::from_xyz(1.0, 2.0, 3.0).looking_at(Vec3::ZERO, Vec3::Y),
+    ));
+    commands.insert_resource(AmbientLight {
+        brightness: 400.0,
+        ..default()
+    });
+
     commands.spawn((
         // The `PrimaryEguiContext` component requires everything needed to render a primary context.
         PrimaryEguiContext,
@@ -69,96 +162,232 @@ fn setup(
     ));
 }
 
+/// Marker for the MD2 instance controlled by the keyboard and UI
+#[derive(Component)]
+struct Selected;
+
 fn keyboard_input_system(
     mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
     asset_server: Res<AssetServer>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut query: Query<(Entity, &mut MD2Component)>,
+    md2_assets: Res<Assets<Md2Asset>>,
+    mut skin_materials: ResMut<Md2SkinMaterials>,
+    mut materials: ResMut<Assets<Md2Material>>,
+    mut query: Query<(Entity, &Md2Model, &mut MD2Component), With<Selected>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::KeyZ) {
-        let (entity, mut md2) = query.single_mut().unwrap();
-        let new_mat = md2.next_skin(&asset_server, &mut materials);
-        commands.entity(entity).insert(new_mat);
-    }
+    for (entity, model, mut md2) in &mut query {
+        let Some(asset) = md2_assets.get(&model.0) else {
+            continue;
+        };
 
-    if keyboard_input.just_pressed(KeyCode::KeyX) {
-        let (_, mut md2) = query.single_mut().unwrap();
-        md2.next_anim();
-    }
-}
+        if keyboard_input.just_pressed(KeyCode::KeyZ) {
+            let new_mat = md2.next_skin(asset, &mut skin_materials, &asset_server, &mut materials);
+            commands.entity(entity).insert(new_mat);
+        }
 
-fn animation_system(
-    time: Res<Time>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut query: Query<(&Mesh3d, &mut MD2Component)>,
-) {
-    let (mesh, mut md2) = query.single_mut().unwrap();
-    let vertices = md2.animate(time.delta_secs());
-    let m = meshes.get_mut(mesh.id()).unwrap();
-    m.insert_attribute(Mesh::ATTRIBUTE_POSITION, vertices);
+        if keyboard_input.just_pressed(KeyCode::KeyX) {
+            md2.next_anim(asset);
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn ui_system(
     mut contexts: EguiContexts,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
     mut md2s: ResMut<MD2Resource>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut query: Query<(Entity, &mut MD2Component)>,
+    md2_assets: Res<Assets<Md2Asset>>,
+    mut skin_materials: ResMut<Md2SkinMaterials>,
+    mut materials: ResMut<Assets<Md2Material>>,
+    mut spawn_pos: Local<Vec3>,
+    instances: Query<(Entity, &Name, Has<Selected>), (With<Md2Model>, Without<Md2Weapon>)>,
+    mut selected: Query<(Entity, &Md2Model, &Transform, Option<&mut MD2Component>), With<Selected>>,
 ) -> Result {
-    let (entity, mut md2) = query.single_mut()?;
-    let mut curr_skin = md2.skin_idx;
-    let mut curr_anim = md2.anim_idx;
+    let mut select = None;
     let mut curr_md2 = md2s.curr_idx;
 
     egui::Window::new("MD2").show(contexts.ctx_mut()?, |ui| {
+        for (entity, name, is_selected) in &instances {
+            if ui
+                .selectable_label(is_selected, format!("{} ({})", name, entity))
+                .clicked()
+            {
+                select = Some(entity);
+            }
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("position");
+            ui.add(egui::DragValue::new(&mut spawn_pos.x).speed(0.1));
+            ui.add(egui::DragValue::new(&mut spawn_pos.y).speed(0.1));
+            ui.add(egui::DragValue::new(&mut spawn_pos.z).speed(0.1));
+        });
+
+        ui.horizontal(|ui| {
+            if let Some(path) = md2s.curr_path()
+                && ui.button("spawn").clicked()
+            {
+                let handle = asset_server.load(path);
+                select = Some(spawn_md2(handle, *spawn_pos, &mut commands));
+            }
+
+            if let Ok((entity, model, _, _)) = selected.single() {
+                if ui.button("duplicate").clicked() {
+                    select = Some(spawn_md2(model.0.clone(), *spawn_pos, &mut commands));
+                }
+
+                if ui.button("despawn").clicked() {
+                    commands.entity(entity).despawn();
+                }
+            }
+        });
+
+        ui.separator();
+
         egui::ComboBox::from_label("md2")
             .selected_text(md2s.curr_name())
             .show_ui(ui, |ui| {
                 for (idx, name) in md2s.names.iter().enumerate() {
-                    ui.selectable_value(&mut curr_md2, idx, name);
+                    ui.selectable_value(&mut curr_md2, Some(idx), name);
                 }
             });
 
-        if curr_md2 != md2s.curr_idx {
-            md2s.curr_idx = curr_md2;
+        // Remembered for "spawn" even without a selected instance
+        let md2_changed = curr_md2 != md2s.curr_idx;
+        md2s.curr_idx = curr_md2;
+
+        let Ok((entity, model, transform, md2)) = selected.single_mut() else {
+            return;
+        };
+
+        // Replace the selected instance with the new model
+        if md2_changed && let Some(path) = md2s.curr_path() {
             commands.entity(entity).despawn();
-            spawn_md2(
-                md2s.curr_path(),
-                &mut commands,
-                &asset_server,
-                &mut materials,
-                &mut meshes,
-            );
+            let handle = asset_server.load(path);
+            select = Some(spawn_md2(handle, transform.translation, &mut commands));
         }
 
+        // Nothing more to show until the model has finished loading
+        let (Some(mut md2), Some(asset)) = (md2, md2_assets.get(&model.0)) else {
+            return;
+        };
+        let mut curr_skin = md2.skin_idx;
+        let mut curr_anim = md2.anim_idx;
+
         egui::ComboBox::from_label("skin")
-            .selected_text(md2.skin_name())
+            .selected_text(md2.skin_name(asset))
             .show_ui(ui, |ui| {
-                for (idx, skin) in md2.skins().iter().enumerate() {
+                for (idx, skin) in asset.skins().iter().enumerate() {
                     ui.selectable_value(&mut curr_skin, idx, &skin.name);
                 }
             });
 
         if curr_skin != md2.skin_idx {
-            let new_mat = md2.set_skin_idx(curr_skin, &asset_server, &mut materials);
+            let new_mat = md2.set_skin_idx(
+                curr_skin,
+                asset,
+                &mut skin_materials,
+                &asset_server,
+                &mut materials,
+            );
+            commands.entity(entity).insert(new_mat);
+        }
+
+        // Only has an effect on palette skins, see Md2LoaderSettings::palette_skins
+        let mut player_colors = md2.player_colors();
+        let mut translate = player_colors.is_some();
+        ui.checkbox(&mut translate, "player colors");
+        if translate {
+            let colors = player_colors.get_or_insert_default();
+            ui.add(egui::Slider::new(&mut colors.shirt, 0..=PALETTE_RAMPS - 1).text("shirt"));
+            ui.add(egui::Slider::new(&mut colors.pants, 0..=PALETTE_RAMPS - 1).text("pants"));
+        } else {
+            player_colors = None;
+        }
+
+        if player_colors != md2.player_colors() {
+            let new_mat = md2.set_player_colors(
+                player_colors,
+                asset,
+                &mut skin_materials,
+                &asset_server,
+                &mut materials,
+            );
             commands.entity(entity).insert(new_mat);
         }
 
         egui::ComboBox::from_label("anim")
-            .selected_text(md2.anim_name())
+            .selected_text(md2.anim_name(asset))
             .show_ui(ui, |ui| {
-                for (idx, anim) in md2.animations().iter().enumerate() {
+                for (idx, anim) in asset.animations().iter().enumerate() {
                     ui.selectable_value(&mut curr_anim, idx, &anim.name);
                 }
             });
 
         if curr_anim != md2.anim_idx {
-            md2.set_anim_idx(curr_anim);
+            md2.fade_to_anim(asset, curr_anim);
+        }
+
+        ui.add(egui::Slider::new(&mut md2.fade_duration, 0.0..=1.0).text("fade"));
+
+        ui.add(egui::Slider::new(&mut md2.fps, 1.0..=30.0).text("fps"));
+
+        let loop_modes = [
+            (LoopMode::Loop, "loop"),
+            (LoopMode::Once, "once"),
+            (LoopMode::PingPong, "ping-pong"),
+        ];
+        let mut loop_mode = md2.loop_mode();
+        egui::ComboBox::from_label("loop")
+            .selected_text(loop_modes.iter().find(|(m, _)| *m == loop_mode).unwrap().1)
+            .show_ui(ui, |ui| {
+                for (mode, name) in loop_modes {
+                    ui.selectable_value(&mut loop_mode, mode, name);
+                }
+            });
+
+        if loop_mode != md2.loop_mode() {
+            md2.set_loop_mode(loop_mode);
+        }
+
+        // Pausing and stepping lets individual key frames be reviewed
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut md2.paused, "paused");
+
+            if ui.button("<").clicked() {
+                md2.paused = true;
+                md2.step(asset, false);
+            }
+
+            if ui.button(">").clicked() {
+                md2.paused = true;
+                md2.step(asset, true);
+            }
+        });
+
+        let mut frame = md2.curr_frame();
+        let mut interp = md2.interp();
+        let last_frame = md2.num_anim_frames(asset) - 1;
+        let frame_changed = ui
+            .add(egui::Slider::new(&mut frame, 0..=last_frame).text(md2.frame_name(asset)))
+            .changed();
+        let interp_changed = ui
+            .add(egui::Slider::new(&mut interp, 0.0..=1.0).text("interp"))
+            .changed();
+
+        if frame_changed || interp_changed {
+            md2.paused = true;
+            md2.seek(asset, frame, interp);
         }
     });
 
+    if let Some(entity) = select {
+        for (prev, ..) in &selected {
+            commands.entity(prev).remove::<Selected>();
+        }
+        commands.entity(entity).insert(Selected);
+    }
+
     Ok(())
 }
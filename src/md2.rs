@@ -1,64 +1,218 @@
 //! MD2 file loading and compenent
 use bevy::{
-    asset::{AssetPath, RenderAssetUsages},
+    asset::{AssetLoader, AssetPath, LoadContext, RenderAssetUsages},
+    mesh::{Indices, MeshTag},
     prelude::*,
-    render::render_resource::PrimitiveTopology,
+    render::render_resource::{PrimitiveTopology, TextureFormat},
+    tasks::futures_lite::{AsyncReadExt, StreamExt},
 };
 
+use crate::pak::{self, PAK_SOURCE};
+use crate::pcx::{self, FULLBRIGHT_LABEL, PALETTE_LABEL, PcxLoaderSettings};
+use anorms::ANORMS;
 use glob::glob;
+use material::{
+    ATTRIBUTE_NEXT_NORMAL, ATTRIBUTE_NEXT_POSITION, Md2Material, Md2MorphExtension, PlayerColors,
+};
 use rand::prelude::*;
-use std::collections::HashMap;
-use std::fs;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+mod anorms;
+pub mod gltf;
+pub mod material;
+pub mod state_machine;
+pub mod writer;
+
 #[derive(Debug, Error)]
 pub enum Md2LoaderError {
     #[error("Failed to read MD2 file: {0}")]
     Io(#[from] std::io::Error),
     #[error("Invalid MD2 format: {0}")]
     InvalidFormat(String),
+    #[error("Bad MD2 magic {0:?}, expected \"IDP2\"")]
+    BadMagic([u8; 4]),
+    #[error("Unsupported MD2 version {0}, expected 8")]
+    UnsupportedVersion(i32),
+    #[error(
+        "MD2 {section} section ({count} at offset {offset}) is outside the {file_len} byte file"
+    )]
+    SectionOutOfBounds {
+        section: &'static str,
+        offset: usize,
+        count: usize,
+        file_len: usize,
+    },
+    #[error("MD2 triangle {triangle} references {kind} {index} out of {count}")]
+    IndexOutOfBounds {
+        triangle: usize,
+        kind: &'static str,
+        index: usize,
+        count: usize,
+    },
 }
 
+/// "IDP2" magic number
+const MD2_IDENT: [u8; 4] = *b"IDP2";
+const MD2_VERSION: i32 = 8;
+const HDR_BYTES: usize = 68;
+
+/// Size of a skin path in the skin table
+const SKIN_NAME_BYTES: usize = 64;
+
 /// MD2 file header
+///
+/// All counts and offsets are validated against
+/// the file length when the header is parsed.
 #[derive(Debug)]
-#[repr(C)]
 struct Header {
-    ident: i32,
-    version: i32,
     skinwidth: i32,
     skinheight: i32,
-    framesize: i32,
-    num_skins: i32,
-    num_xyz: i32,
-    num_st: i32,
-    num_tris: i32,
-    num_glcmds: i32,
-    num_frames: i32,
-    offset_skins: i32,
-    offset_st: i32,
-    offset_tris: i32,
-    offset_frames: i32,
-    offset_glcmd: i32,
-    offset_end: i32,
+    framesize: usize,
+    num_skins: usize,
+    num_xyz: usize,
+    num_st: usize,
+    num_tris: usize,
+    num_glcmds: usize,
+    num_frames: usize,
+    offset_skins: usize,
+    offset_st: usize,
+    offset_tris: usize,
+    offset_frames: usize,
+    offset_glcmd: usize,
 }
 
 impl Header {
     fn from_bytes(data: &[u8]) -> Result<Header, Md2LoaderError> {
-        assert!(std::mem::size_of::<Header>() == 68);
-
-        if data.len() < std::mem::size_of::<Header>() {
+        if data.len() < HDR_BYTES {
             return Err(Md2LoaderError::InvalidFormat(
                 "Not enough bytes for header".to_string(),
             ));
         }
 
-        let hdr_bytes: [u8; std::mem::size_of::<Header>()] =
-            data[0..std::mem::size_of::<Header>()].try_into().unwrap();
-        // TODO: remove unsafe block
-        let header: Header = unsafe { std::mem::transmute(hdr_bytes) };
+        let ident: [u8; 4] = data[0..4].try_into().unwrap();
+        if ident != MD2_IDENT {
+            return Err(Md2LoaderError::BadMagic(ident));
+        }
+
+        let field = |idx: usize| {
+            let off = idx * 4;
+            i32::from_le_bytes(data[off..off + 4].try_into().unwrap())
+        };
+
+        let version = field(1);
+        if version != MD2_VERSION {
+            return Err(Md2LoaderError::UnsupportedVersion(version));
+        }
+
+        let unsigned = |idx: usize, what: &str| {
+            usize::try_from(field(idx))
+                .map_err(|err| Md2LoaderError::InvalidFormat(format!("Invalid {} - {}", what, err)))
+        };
+
+        let header = Header {
+            skinwidth: field(2),
+            skinheight: field(3),
+            framesize: unsigned(4, "frame size")?,
+            num_skins: unsigned(5, "number of skins")?,
+            num_xyz: unsigned(6, "number of vertices")?,
+            num_st: unsigned(7, "number of texcoords")?,
+            num_tris: unsigned(8, "number of triangles")?,
+            num_glcmds: unsigned(9, "number of gl commands")?,
+            num_frames: unsigned(10, "number of frames")?,
+            offset_skins: unsigned(11, "skins offset")?,
+            offset_st: unsigned(12, "texcoords offset")?,
+            offset_tris: unsigned(13, "triangles offset")?,
+            offset_frames: unsigned(14, "frames offset")?,
+            offset_glcmd: unsigned(15, "gl commands offset")?,
+        };
+
+        if header.skinwidth <= 0 || header.skinheight <= 0 {
+            return Err(Md2LoaderError::InvalidFormat(format!(
+                "Invalid skin size {}x{}",
+                header.skinwidth, header.skinheight
+            )));
+        }
+
+        if header.num_frames == 0 {
+            return Err(Md2LoaderError::InvalidFormat(
+                "Model has no frames".to_string(),
+            ));
+        }
+
+        let min_framesize =
+            std::mem::size_of::<Frame>() + header.num_xyz * std::mem::size_of::<Vertex>();
+        if header.framesize < min_framesize {
+            return Err(Md2LoaderError::InvalidFormat(format!(
+                "Frame size {} is too small for {} vertices",
+                header.framesize, header.num_xyz
+            )));
+        }
+
+        Header::check_section(
+            data,
+            "skins",
+            header.offset_skins,
+            header.num_skins,
+            SKIN_NAME_BYTES,
+        )?;
+        Header::check_section(
+            data,
+            "texcoords",
+            header.offset_st,
+            header.num_st,
+            std::mem::size_of::<TexCoord>(),
+        )?;
+        Header::check_section(
+            data,
+            "triangles",
+            header.offset_tris,
+            header.num_tris,
+            std::mem::size_of::<Triangle>(),
+        )?;
+        Header::check_section(
+            data,
+            "frames",
+            header.offset_frames,
+            header.num_frames,
+            header.framesize,
+        )?;
+        Header::check_section(
+            data,
+            "gl commands",
+            header.offset_glcmd,
+            header.num_glcmds,
+            4,
+        )?;
+
         Ok(header)
     }
+
+    /// Ensure `count` elements of `size` bytes at `offset` are within the file
+    fn check_section(
+        data: &[u8],
+        section: &'static str,
+        offset: usize,
+        count: usize,
+        size: usize,
+    ) -> Result<(), Md2LoaderError> {
+        let end = count
+            .checked_mul(size)
+            .and_then(|len| len.checked_add(offset));
+
+        match end {
+            Some(end) if end <= data.len() => Ok(()),
+            _ => Err(Md2LoaderError::SectionOutOfBounds {
+                section,
+                offset,
+                count,
+                file_len: data.len(),
+            }),
+        }
+    }
 }
 
 /// Scaled texture coordinates
@@ -175,95 +329,470 @@ impl Frame {
         })
     }
 
-    fn get_name(&self) -> String {
-        let s = String::from_utf8_lossy(&self.name);
-        let mut end = s.len();
-        if let Some(index) = s.find(|c: char| c.is_ascii_digit() || c.is_ascii_control()) {
-            end = index;
-        }
-
-        s[0..end].to_string()
+    /// Full frame name, e.g. "run3"
+    fn raw_name(&self) -> String {
+        let end = self
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.name.len());
+        String::from_utf8_lossy(&self.name[0..end]).to_string()
     }
 }
 
-type KeyFrame = Vec<Vec3>;
-
-/// Decompressed animation key frame
+/// Decompressed key frame vertices
 ///
-/// For simplicity, this directly stores all the
-/// 3d vertices per frame in the animation.
-#[derive(Debug)]
+/// There is one entry per MD2 vertex, the [`MeshLayout`]
+/// maps them to the vertices of the render mesh.
+#[derive(Debug, Clone)]
+pub struct KeyFrame {
+    /// Original frame name from the file, e.g. "run3"
+    pub name: String,
+    pub positions: Vec<Vec3>,
+    pub normals: Vec<Vec3>,
+}
+
+impl KeyFrame {
+    /// Interpolate between two key frames
+    pub fn lerp(&self, other: &KeyFrame, interp: f32) -> KeyFrame {
+        let positions = self
+            .positions
+            .iter()
+            .zip(&other.positions)
+            .map(|(a, b)| a.lerp(*b, interp))
+            .collect();
+        let normals = self
+            .normals
+            .iter()
+            .zip(&other.normals)
+            .map(|(a, b)| a.lerp(*b, interp).normalize_or_zero())
+            .collect();
+
+        KeyFrame {
+            name: self.name.clone(),
+            positions,
+            normals,
+        }
+    }
+}
+
+/// Decompressed animation key frames
+#[derive(Debug, Clone)]
 pub struct Animation {
     pub name: String,
     pub key_frames: Vec<KeyFrame>,
 }
 
+/// Vertex layout of the render mesh
+///
+/// Each mesh vertex is a unique (vertex index, texcoord index) pair
+/// from the MD2 triangles, so vertices are only duplicated along
+/// texture seams.
+#[derive(Debug, Clone)]
+pub struct MeshLayout {
+    /// Key frame vertex index of each mesh vertex
+    pub vertex_map: Vec<u32>,
+    pub texcoords: Vec<Vec2>,
+    /// Triangle list indices into the mesh vertices
+    pub indices: Vec<u32>,
+}
+
+impl MeshLayout {
+    /// Reorder per key frame vertex values into mesh vertex order
+    pub fn gather<T: Copy>(&self, values: &[T]) -> Vec<T> {
+        self.vertex_map
+            .iter()
+            .map(|&i| values[i as usize])
+            .collect()
+    }
+
+    /// Layout built from the GL command strips and fans
+    ///
+    /// Vertices are only split where their float texcoords differ.
+    pub fn from_gl_commands(commands: &[GlCommand]) -> MeshLayout {
+        let mut unique: HashMap<(u32, [u32; 2]), u32> = HashMap::new();
+        let mut layout = MeshLayout {
+            vertex_map: Vec::new(),
+            texcoords: Vec::new(),
+            indices: Vec::new(),
+        };
+
+        for triangle in commands.iter().flat_map(GlCommand::triangles) {
+            for vertex in triangle {
+                let key = (vertex.index, vertex.texcoord.to_array().map(f32::to_bits));
+                let index = *unique.entry(key).or_insert_with(|| {
+                    layout.vertex_map.push(vertex.index);
+                    layout.texcoords.push(vertex.texcoord);
+                    (layout.texcoords.len() - 1) as u32
+                });
+                layout.indices.push(index);
+            }
+        }
+
+        layout
+    }
+
+    /// Key frame vertex indices and texcoords of each triangle corner
+    ///
+    /// Corners are rotated to start at the lowest vertex index so the
+    /// same triangle gives the same key regardless of its first corner.
+    fn corner_triangles(&self) -> impl Iterator<Item = ([u32; 3], [Vec2; 3])> {
+        self.indices.chunks_exact(3).map(|tri| {
+            let xyz = [0, 1, 2].map(|i| self.vertex_map[tri[i] as usize]);
+            let first = (0..3).min_by_key(|&i| xyz[i]).unwrap();
+            let corner = |i: usize| tri[(first + i) % 3] as usize;
+            (
+                [0, 1, 2].map(|i| self.vertex_map[corner(i)]),
+                [0, 1, 2].map(|i| self.texcoords[corner(i)]),
+            )
+        })
+    }
+
+    /// Compare the triangles of two layouts by key frame vertex
+    pub fn compare(&self, other: &MeshLayout) -> LayoutComparison {
+        let mut others: HashMap<[u32; 3], Vec<[Vec2; 3]>> = HashMap::new();
+        for (xyz, uv) in other.corner_triangles() {
+            others.entry(xyz).or_default().push(uv);
+        }
+
+        let mut comparison = LayoutComparison {
+            triangles: [self.indices.len() / 3, other.indices.len() / 3],
+            vertices: [self.texcoords.len(), other.texcoords.len()],
+            ..default()
+        };
+
+        for (xyz, uv) in self.corner_triangles() {
+            if let Some(other_uv) = others.get_mut(&xyz).and_then(Vec::pop) {
+                for (a, b) in uv.iter().zip(&other_uv) {
+                    let delta = (a - b).abs().max_element();
+                    comparison.max_texcoord_delta = comparison.max_texcoord_delta.max(delta);
+                }
+                continue;
+            }
+
+            // The same corners in the opposite order, again starting at the lowest index
+            let reversed = [xyz[0], xyz[2], xyz[1]];
+            match others.get_mut(&reversed).and_then(Vec::pop) {
+                Some(_) => comparison.flipped += 1,
+                None => comparison.missing += 1,
+            }
+        }
+        comparison.extra = others.values().map(Vec::len).sum();
+
+        comparison
+    }
+
+    /// Index buffer, using 16 bit indices when possible
+    pub fn mesh_indices(&self) -> Indices {
+        if self.texcoords.len() <= usize::from(u16::MAX) + 1 {
+            Indices::U16(self.indices.iter().map(|&i| i as u16).collect())
+        } else {
+            Indices::U32(self.indices.clone())
+        }
+    }
+}
+
+/// Primitive drawn by a [`GlCommand`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlCommandKind {
+    Strip,
+    Fan,
+}
+
+/// GL command vertex with its own float texture coordinates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlVertex {
+    pub texcoord: Vec2,
+    /// Key frame vertex index
+    pub index: u32,
+}
+
+/// Triangle strip or fan from the MD2 GL command list
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlCommand {
+    pub kind: GlCommandKind,
+    pub vertices: Vec<GlVertex>,
+}
+
+impl GlCommand {
+    /// Triangles of the strip or fan, keeping the winding of the first one
+    pub fn triangles(&self) -> impl Iterator<Item = [&GlVertex; 3]> {
+        let v = &self.vertices;
+        (0..v.len().saturating_sub(2)).map(move |i| match self.kind {
+            GlCommandKind::Fan => [&v[0], &v[i + 1], &v[i + 2]],
+            GlCommandKind::Strip if i % 2 == 0 => [&v[i], &v[i + 1], &v[i + 2]],
+            GlCommandKind::Strip => [&v[i + 1], &v[i], &v[i + 2]],
+        })
+    }
+}
+
+/// Differences between two [`MeshLayout`]s of the same model
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LayoutComparison {
+    pub triangles: [usize; 2],
+    pub vertices: [usize; 2],
+    /// Triangles of the first layout missing from the second
+    pub missing: usize,
+    /// Triangles only found in the second layout
+    pub extra: usize,
+    /// Triangles found in both with opposite winding
+    pub flipped: usize,
+    /// Largest texcoord difference between corners of matching triangles
+    pub max_texcoord_delta: f32,
+}
+
+impl std::fmt::Display for LayoutComparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "triangles: {} / {}",
+            self.triangles[0], self.triangles[1]
+        )?;
+        writeln!(f, "vertices: {} / {}", self.vertices[0], self.vertices[1])?;
+        writeln!(f, "missing: {}", self.missing)?;
+        writeln!(f, "extra: {}", self.extra)?;
+        writeln!(f, "flipped: {}", self.flipped)?;
+        write!(f, "max texcoord delta: {}", self.max_texcoord_delta)
+    }
+}
+
 /// On-disk skin data
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Skin {
     pub name: String,
-    pub path: PathBuf,
+    pub path: AssetPath<'static>,
+    /// Path as stored in the MD2 header, `None` for skins found by
+    /// searching the model's directory
+    pub header_path: Option<String>,
+    /// Load as palette indices, see [`Md2LoaderSettings::palette_skins`]
+    pub indexed: bool,
+    /// Glow where the fullbright colors are used, see [`Md2LoaderSettings::fullbright_skins`]
+    pub fullbright: bool,
 }
 
-/// MD2 model
-#[derive(Debug)]
-struct MD2 {
+impl Skin {
+    fn is_pcx(&self) -> bool {
+        self.path.get_full_extension().as_deref() == Some("pcx")
+    }
+}
+
+/// MD2 model asset
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct Md2Asset {
     animations: Vec<Animation>,
-    texcoords: Vec<Vec2>,
+    layout: MeshLayout,
     skins: Vec<Skin>,
+    gl_commands: Vec<GlCommand>,
+    /// Skin size in pixels, texcoords are stored in this resolution
+    skin_size: UVec2,
+    /// Companion weapon model of a player model
+    weapon: Option<Handle<Md2Asset>>,
 }
 
-impl MD2 {
-    pub fn load(fpath: &Path) -> Result<MD2, Md2LoaderError> {
-        let data = fs::read(fpath)?;
-        let header = Header::from_bytes(&data)?;
-        let triangles = MD2::load_triangles(&data, &header)?;
-        let texcoords = MD2::load_texcoords(&data, &header, &triangles)?;
-        let animations = MD2::load_animations(&data, &header, &triangles)?;
-        let skins = MD2::find_skins(fpath); // skins - only from directory right now
+impl Md2Asset {
+    pub fn skins(&self) -> &[Skin] {
+        &self.skins
+    }
+
+    pub fn animations(&self) -> &[Animation] {
+        &self.animations
+    }
+
+    pub fn layout(&self) -> &MeshLayout {
+        &self.layout
+    }
+
+    pub fn skin_size(&self) -> UVec2 {
+        self.skin_size
+    }
+
+    /// Strips and fans of the GL command list, empty if the file has none
+    pub fn gl_commands(&self) -> &[GlCommand] {
+        &self.gl_commands
+    }
+
+    /// Replace the triangle list layout with one built from the GL commands
+    pub fn use_gl_commands(&mut self) -> Result<(), Md2LoaderError> {
+        if self.gl_commands.is_empty() {
+            return Err(Md2LoaderError::InvalidFormat(
+                "Model has no GL commands".to_string(),
+            ));
+        }
+
+        self.layout = MeshLayout::from_gl_commands(&self.gl_commands);
+        Ok(())
+    }
+
+    /// Weapon model found next to a player model by the [`Md2Loader`]
+    pub fn weapon(&self) -> Option<&Handle<Md2Asset>> {
+        self.weapon.as_ref()
+    }
+
+    /// Skin to use alongside skin `idx` of `other`
+    ///
+    /// A skin with the same name is preferred, then the same index.
+    pub fn matching_skin(&self, other: &Md2Asset, idx: usize) -> usize {
+        let Some(name) = other.skins.get(idx).map(|skin| &skin.name) else {
+            return 0;
+        };
+        self.skins
+            .iter()
+            .position(|skin| skin.name == *name)
+            .unwrap_or(if idx < self.skins.len() { idx } else { 0 })
+    }
+
+    /// Total number of key frames over all animations
+    pub fn num_key_frames(&self) -> usize {
+        self.animations
+            .iter()
+            .map(|anim| anim.key_frames.len())
+            .sum()
+    }
 
-        Ok(MD2 {
+    /// Parse an MD2 model from the raw file contents
+    ///
+    /// Skin paths are as stored in the file, relative to the game
+    /// directory. They are resolved against the model by the [`Md2Loader`].
+    pub fn from_bytes(data: &[u8]) -> Result<Md2Asset, Md2LoaderError> {
+        let header = Header::from_bytes(data)?;
+        let triangles = Md2Asset::load_triangles(data, &header)?;
+        let layout = Md2Asset::load_mesh_layout(data, &header, &triangles)?;
+        let animations = Md2Asset::load_animations(data, &header)?;
+        let skins = Md2Asset::load_skins(data, &header)?;
+        let gl_commands = Md2Asset::load_gl_commands(data, &header)?;
+
+        Ok(Md2Asset {
             animations,
-            texcoords,
+            layout,
             skins,
+            gl_commands,
+            skin_size: UVec2::new(header.skinwidth as u32, header.skinheight as u32),
+            weapon: None,
         })
     }
 
-    fn load_triangles(data: &[u8], header: &Header) -> Result<Vec<Triangle>, Md2LoaderError> {
-        let num_tris = usize::try_from(header.num_tris).map_err(|err| {
-            Md2LoaderError::InvalidFormat(format!("Invalid number of triangles - {}", err))
-        })?;
-        let tris_off = usize::try_from(header.offset_tris).map_err(|err| {
-            Md2LoaderError::InvalidFormat(format!("Invalid triangles offset - {}", err))
-        })?;
+    fn load_skins(data: &[u8], header: &Header) -> Result<Vec<Skin>, Md2LoaderError> {
+        let mut skins = Vec::with_capacity(header.num_skins);
+
+        for i in 0..header.num_skins {
+            let off = header.offset_skins + (i * SKIN_NAME_BYTES);
+            let bytes = &data[off..off + SKIN_NAME_BYTES];
+            let end = bytes
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(SKIN_NAME_BYTES);
+            let path = String::from_utf8_lossy(&bytes[0..end]).to_string();
+
+            let Some(name) = Path::new(&path).file_stem() else {
+                continue;
+            };
+
+            skins.push(Skin {
+                name: name.to_string_lossy().to_string(),
+                path: AssetPath::from(path.clone()),
+                header_path: Some(path),
+                indexed: false,
+                fullbright: false,
+            });
+        }
+
+        Ok(skins)
+    }
+
+    /// Parse the GL command list up to its terminating zero
+    ///
+    /// Each command is a vertex count, negative for fans, followed by
+    /// float s and t and a vertex index for every vertex.
+    fn load_gl_commands(data: &[u8], header: &Header) -> Result<Vec<GlCommand>, Md2LoaderError> {
+        let words: Vec<i32> = data
+            [header.offset_glcmd..header.offset_glcmd + header.num_glcmds * 4]
+            .chunks_exact(4)
+            .map(|word| i32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+
+        let mut commands = Vec::new();
+        let mut pos = 0;
+        while let Some(&count) = words.get(pos)
+            && count != 0
+        {
+            let kind = if count > 0 {
+                GlCommandKind::Strip
+            } else {
+                GlCommandKind::Fan
+            };
+            let num_vertices = count.unsigned_abs() as usize;
+            let Some(vertex_words) = words.get(pos + 1..pos + 1 + num_vertices * 3) else {
+                return Err(Md2LoaderError::InvalidFormat(format!(
+                    "GL command at {} with {} vertices is truncated",
+                    pos, num_vertices
+                )));
+            };
+
+            let mut vertices = Vec::with_capacity(num_vertices);
+            for vertex in vertex_words.chunks_exact(3) {
+                let index = vertex[2] as u32;
+                if index as usize >= header.num_xyz {
+                    return Err(Md2LoaderError::InvalidFormat(format!(
+                        "GL command at {} references vertex {} out of {}",
+                        pos, vertex[2], header.num_xyz
+                    )));
+                }
+
+                vertices.push(GlVertex {
+                    texcoord: Vec2::new(
+                        f32::from_bits(vertex[0] as u32),
+                        f32::from_bits(vertex[1] as u32),
+                    ),
+                    index,
+                });
+            }
+
+            commands.push(GlCommand { kind, vertices });
+            pos += 1 + num_vertices * 3;
+        }
 
-        let mut triangles = Vec::with_capacity(num_tris);
+        Ok(commands)
+    }
+
+    fn load_triangles(data: &[u8], header: &Header) -> Result<Vec<Triangle>, Md2LoaderError> {
+        let mut triangles = Vec::with_capacity(header.num_tris);
 
         for i in 0..header.num_tris {
-            let off = tris_off + (i as usize * std::mem::size_of::<Triangle>());
+            let off = header.offset_tris + (i * std::mem::size_of::<Triangle>());
             let triangle = Triangle::from_bytes(&data[off..])?;
+
+            for j in 0..3 {
+                let checks = [
+                    ("vertex", usize::from(triangle.vertex[j]), header.num_xyz),
+                    ("texcoord", usize::from(triangle.st[j]), header.num_st),
+                ];
+                for (kind, index, count) in checks {
+                    if index >= count {
+                        return Err(Md2LoaderError::IndexOutOfBounds {
+                            triangle: i,
+                            kind,
+                            index,
+                            count,
+                        });
+                    }
+                }
+            }
+
             triangles.push(triangle);
         }
 
         Ok(triangles)
     }
 
-    fn load_texcoords(
+    fn load_mesh_layout(
         data: &[u8],
         header: &Header,
         triangles: &Vec<Triangle>,
-    ) -> Result<Vec<Vec2>, Md2LoaderError> {
-        let num_st = usize::try_from(header.num_st).map_err(|err| {
-            Md2LoaderError::InvalidFormat(format!("Invalid number of texcoords - {}", err))
-        })?;
-        let st_off = usize::try_from(header.offset_st).map_err(|err| {
-            Md2LoaderError::InvalidFormat(format!("Invalid texcoords offset - {}", err))
-        })?;
-
-        let mut unscaled_texcoords = Vec::with_capacity(num_st);
-
-        for i in 0..num_st {
-            let off = st_off + (i * std::mem::size_of::<TexCoord>());
+    ) -> Result<MeshLayout, Md2LoaderError> {
+        let mut unscaled_texcoords = Vec::with_capacity(header.num_st);
+
+        for i in 0..header.num_st {
+            let off = header.offset_st + (i * std::mem::size_of::<TexCoord>());
             let texcoord = TexCoord::from_bytes(&data[off..])?;
             unscaled_texcoords.push(texcoord);
         }
@@ -271,278 +800,1197 @@ impl MD2 {
         let skin_width = header.skinwidth as f32;
         let skin_height = header.skinheight as f32;
 
-        let mut texcoords = Vec::with_capacity(triangles.len() * 3);
+        // Vertices are only split where they have different texcoords
+        let mut unique: HashMap<(u16, u16), u32> = HashMap::new();
+        let mut layout = MeshLayout {
+            vertex_map: Vec::new(),
+            texcoords: Vec::new(),
+            indices: Vec::with_capacity(triangles.len() * 3),
+        };
 
         for tri in triangles {
             for i in 0..3 {
-                let index = usize::from(tri.st[i]);
-                let texcoord = &unscaled_texcoords[index];
-                let s = f32::from(texcoord.s) / skin_width;
-                let t = f32::from(texcoord.t) / skin_height;
-                texcoords.push(Vec2::new(s, t));
+                let key = (tri.vertex[i], tri.st[i]);
+                let index = *unique.entry(key).or_insert_with(|| {
+                    let texcoord = &unscaled_texcoords[usize::from(tri.st[i])];
+                    let s = f32::from(texcoord.s) / skin_width;
+                    let t = f32::from(texcoord.t) / skin_height;
+                    layout.vertex_map.push(u32::from(tri.vertex[i]));
+                    layout.texcoords.push(Vec2::new(s, t));
+                    (layout.texcoords.len() - 1) as u32
+                });
+                layout.indices.push(index);
             }
         }
 
-        Ok(texcoords)
+        Ok(layout)
     }
 
     fn read_and_decompress_vertices(
         data: &[u8],
         num_xyz: usize,
         frame: &Frame,
-        triangles: &Vec<Triangle>,
-    ) -> Result<Vec<Vec3>, Md2LoaderError> {
-        let mut raw_vertices: Vec<Vertex> = Vec::with_capacity(num_xyz);
+    ) -> Result<KeyFrame, Md2LoaderError> {
+        let mut positions = Vec::with_capacity(num_xyz);
+        let mut normals = Vec::with_capacity(num_xyz);
 
         for i in 0..num_xyz {
             let off = i * std::mem::size_of::<Vertex>();
             let vertex = Vertex::from_bytes(&data[off..])?;
-            raw_vertices.push(vertex);
+            let Some(n) = ANORMS.get(usize::from(vertex.normal_index)) else {
+                return Err(Md2LoaderError::InvalidFormat(format!(
+                    "Invalid normal index {}",
+                    vertex.normal_index
+                )));
+            };
+
+            // NB: pay attention to the assingments here as we swap z and y
+            let x = (frame.scale[0] * vertex.v[0] as f32) + frame.translate[0];
+            let z = (frame.scale[1] * vertex.v[1] as f32) + frame.translate[1];
+            let y = (frame.scale[2] * vertex.v[2] as f32) + frame.translate[2];
+            positions.push(Vec3::new(x, y, z));
+
+            // normals get the same swizzle
+            normals.push(Vec3::new(n[0], n[2], n[1]));
         }
 
-        let mut vertices = Vec::with_capacity(triangles.len() * 3);
+        Ok(KeyFrame {
+            name: frame.raw_name(),
+            positions,
+            normals,
+        })
+    }
 
-        for tri in triangles {
-            for i in 0..3 {
-                let vi = usize::from(tri.vertex[i]);
-                let vertex = &raw_vertices[vi];
-                // NB: pay attention to the assingments here as we swap z and y
-                let x = (frame.scale[0] * vertex.v[0] as f32) + frame.translate[0];
-                let z = (frame.scale[1] * vertex.v[1] as f32) + frame.translate[1];
-                let y = (frame.scale[2] * vertex.v[2] as f32) + frame.translate[2];
-                vertices.push(Vec3::new(x, y, z));
-            }
+    fn load_animations(data: &[u8], header: &Header) -> Result<Vec<Animation>, Md2LoaderError> {
+        let mut key_frames = Vec::with_capacity(header.num_frames);
+
+        for i in 0..header.num_frames {
+            let off = header.offset_frames + (i * header.framesize);
+            let frame = Frame::from_bytes(&data[off..])?;
+            let vertices = Md2Asset::read_and_decompress_vertices(
+                &data[off + std::mem::size_of::<Frame>()..],
+                header.num_xyz,
+                &frame,
+            )?;
+            key_frames.push(vertices);
         }
 
-        Ok(vertices)
+        Ok(group_key_frames(key_frames))
     }
 
-    fn load_animations(
-        data: &[u8],
-        header: &Header,
-        triangles: &Vec<Triangle>,
-    ) -> Result<Vec<Animation>, Md2LoaderError> {
-        let num_xyz = usize::try_from(header.num_xyz).map_err(|err| {
-            Md2LoaderError::InvalidFormat(format!("Invalid number of vertices - {}", err))
-        })?;
-        let frames_off = usize::try_from(header.offset_frames).map_err(|err| {
-            Md2LoaderError::InvalidFormat(format!("Invalid frames offset - {}", err))
-        })?;
-
-        let mut key_frames: Vec<KeyFrame> = Vec::new();
-        let mut animations: Vec<Animation> = Vec::new();
-        let mut last_frame_name: Option<String> = None;
-        let mut off = frames_off;
-
-        for _ in 0..header.num_frames {
-            let frame = Frame::from_bytes(&data[off..])?;
-            off += std::mem::size_of::<Frame>();
-            let vertices =
-                MD2::read_and_decompress_vertices(&data[off..], num_xyz, &frame, triangles)?;
-            off += num_xyz * std::mem::size_of::<Vertex>();
-
-            let curr_name = frame.get_name();
-            if let Some(prev_name) = last_frame_name
-                && prev_name != curr_name
-            {
-                animations.push(Animation {
-                    name: prev_name.clone(),
-                    key_frames,
-                });
+    /// Replace the grouping of key frames into animations with an explicit table
+    ///
+    /// Frame ranges index all key frames in file order, frames not
+    /// covered by the table are dropped.
+    pub fn set_animation_table(&mut self, table: &[AnimationRange]) -> Result<(), Md2LoaderError> {
+        let key_frames: Vec<KeyFrame> = self
+            .animations
+            .iter()
+            .flat_map(|anim| anim.key_frames.iter().cloned())
+            .collect();
 
-                key_frames = Vec::new();
+        let mut animations = Vec::with_capacity(table.len());
+        for range in table {
+            if range.first > range.last || range.last >= key_frames.len() {
+                return Err(Md2LoaderError::InvalidFormat(format!(
+                    "Animation {} frames {}..={} are outside the {} key frames",
+                    range.name,
+                    range.first,
+                    range.last,
+                    key_frames.len()
+                )));
             }
-            last_frame_name = Some(curr_name);
 
-            key_frames.push(vertices);
-        }
-
-        if !key_frames.is_empty() {
             animations.push(Animation {
-                name: last_frame_name.unwrap(),
-                key_frames,
+                name: range.name.clone(),
+                key_frames: key_frames[range.first..=range.last].to_vec(),
             });
         }
 
-        Ok(animations)
+        if animations.is_empty() {
+            return Err(Md2LoaderError::InvalidFormat(
+                "Animation table is empty".to_string(),
+            ));
+        }
+
+        self.animations = animations;
+        Ok(())
     }
+}
 
-    fn find_skins(fpath: &Path) -> Vec<Skin> {
-        let extensions = ["*.pcx", "*.png"];
-        let mut skins = HashMap::new();
+/// Split a frame name into the animation name and frame number
+///
+/// Frames are numbered with trailing digits, e.g. "stand01" or "run3". At
+/// most `max_digits` of them are the frame number, any further digits
+/// belong to the animation name, e.g. "pain201" with two is frame 1 of "pain2".
+fn split_frame_name(name: &str, max_digits: usize) -> (&str, Option<u32>) {
+    let number_len = frame_digits(name).min(max_digits);
+    let (base, number) = name.split_at(name.len() - number_len);
+
+    (base.trim_end_matches('_'), number.parse().ok())
+}
+
+/// Number of trailing digits of a frame name
+fn frame_digits(name: &str) -> usize {
+    name.len() - name.trim_end_matches(|c: char| c.is_ascii_digit()).len()
+}
 
-        for ext in extensions {
-            let glob_path = fpath.parent().unwrap().join(ext);
-            let pattern = glob_path.to_str().unwrap();
+/// Frame name without any trailing digits, shared by a run of frames
+fn run_name(name: &str) -> &str {
+    name.trim_end_matches(|c: char| c.is_ascii_digit())
+        .trim_end_matches('_')
+}
+
+/// Number of trailing digits used as the frame number in a run of frames
+///
+/// The longest split is used whose frame numbers never skip ahead within
+/// an animation, so "frame000".."frame197" stays one animation while
+/// "pain101", "pain102", "pain201" are two.
+fn run_number_digits(run: &[KeyFrame]) -> usize {
+    let max_digits = run
+        .iter()
+        .map(|kf| frame_digits(&kf.name))
+        .max()
+        .unwrap_or(0);
+
+    let consistent = |digits: usize| {
+        run.windows(2).all(|pair| {
+            let (a, a_number) = split_frame_name(&pair[0].name, digits);
+            let (b, b_number) = split_frame_name(&pair[1].name, digits);
+            match (a_number, b_number) {
+                (Some(a_number), Some(b_number)) if a == b => b_number <= a_number + 1,
+                _ => true,
+            }
+        })
+    };
 
-            for entry in glob(pattern).unwrap().filter_map(Result::ok) {
-                let path = entry.strip_prefix("assets").unwrap().to_path_buf();
-                let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+    (1..=max_digits)
+        .rev()
+        .find(|&digits| consistent(digits))
+        .unwrap_or(max_digits)
+}
 
-                skins.entry(name).or_insert(path);
+/// Group consecutive key frames into animations by frame name
+///
+/// A new animation starts when the name changes or the frame numbering
+/// starts over, so adjacent sequences with the same name are kept apart.
+/// Repeated animation names get a numeric suffix.
+fn group_key_frames(key_frames: Vec<KeyFrame>) -> Vec<Animation> {
+    let digits: Vec<usize> = key_frames
+        .chunk_by(|a, b| run_name(&a.name) == run_name(&b.name))
+        .flat_map(|run| std::iter::repeat_n(run_number_digits(run), run.len()))
+        .collect();
+
+    let mut animations: Vec<Animation> = Vec::new();
+    let mut last_frame: Option<(String, Option<u32>)> = None;
+
+    for (key_frame, digits) in key_frames.into_iter().zip(digits) {
+        let (name, number) = split_frame_name(&key_frame.name, digits);
+        let restarted = match &last_frame {
+            Some((last_name, last_number)) => {
+                last_name != name || matches!((last_number, number), (Some(l), Some(n)) if n <= *l)
+            }
+            None => true,
+        };
+        last_frame = Some((name.to_string(), number));
+
+        if restarted {
+            let mut unique_name = name.to_string();
+            let mut suffix = 2;
+            while animations.iter().any(|anim| anim.name == unique_name) {
+                unique_name = format!("{}_{}", name, suffix);
+                suffix += 1;
             }
+
+            animations.push(Animation {
+                name: unique_name,
+                key_frames: Vec::new(),
+            });
         }
 
-        skins
-            .iter()
-            .map(|(k, v)| Skin {
-                name: k.clone(),
-                path: v.clone(),
-            })
-            .collect()
+        animations.last_mut().unwrap().key_frames.push(key_frame);
     }
+
+    animations
 }
 
-/// MD2 Bevy Component
-///
-/// Allows changing the current animation and skin.
-#[derive(Component)]
-pub struct MD2Component {
-    md2: MD2,
-    pub skin_idx: usize,
-    pub anim_idx: usize,
-    curr_frame: usize,
-    interp: f32,
-    materials: Vec<Option<Handle<StandardMaterial>>>,
+/// Named range of key frames for [`Md2LoaderSettings::animations`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct AnimationRange {
+    pub name: String,
+    /// Index of the first key frame in the file
+    pub first: usize,
+    /// Index of the last key frame in the file, inclusive
+    pub last: usize,
 }
 
-impl MD2Component {
-    fn load(fpath: &Path) -> Self {
-        let md2 = MD2::load(fpath).unwrap();
-        let skin_idx = rand::rng().random_range(0..md2.skins.len());
-        let anim_idx = rand::rng().random_range(0..md2.animations.len());
-        let materials: Vec<Option<Handle<StandardMaterial>>> = vec![None; md2.skins.len()];
+/// Number of key frames in Quake 2 player models
+const QUAKE2_PLAYER_FRAMES: usize = 198;
+
+/// Frame ranges of Quake 2 player models, from id Software's m_player.h
+const QUAKE2_PLAYER_ANIMATIONS: [(&str, usize, usize); 20] = [
+    ("stand", 0, 39),
+    ("run", 40, 45),
+    ("attack", 46, 53),
+    ("pain1", 54, 57),
+    ("pain2", 58, 61),
+    ("pain3", 62, 65),
+    ("jump", 66, 71),
+    ("flip", 72, 83),
+    ("salute", 84, 94),
+    ("taunt", 95, 111),
+    ("wave", 112, 122),
+    ("point", 123, 134),
+    ("crstnd", 135, 153),
+    ("crwalk", 154, 159),
+    ("crattak", 160, 168),
+    ("crpain", 169, 172),
+    ("crdeath", 173, 177),
+    ("death1", 178, 183),
+    ("death2", 184, 189),
+    ("death3", 190, 197),
+];
+
+/// How the [`Md2Loader`] splits key frames into animations
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Md2AnimationPreset {
+    /// Use [`Md2AnimationPreset::Quake2Player`] for models with 198
+    /// key frames, otherwise group by frame name
+    #[default]
+    Auto,
+    /// Group consecutive key frames by frame name
+    FrameNames,
+    /// Fixed frame ranges of Quake 2 player models, ignoring frame names
+    Quake2Player,
+}
 
-        Self {
-            md2,
-            skin_idx,
-            anim_idx,
-            curr_frame: 0,
-            interp: 0.0,
-            materials,
-        }
+impl Md2AnimationPreset {
+    /// Animation table for a model with `num_frames` key frames
+    ///
+    /// Returns `None` if the key frames are grouped by name.
+    pub fn table(&self, num_frames: usize) -> Option<Vec<AnimationRange>> {
+        let quake2_player = match self {
+            Md2AnimationPreset::Auto => num_frames == QUAKE2_PLAYER_FRAMES,
+            Md2AnimationPreset::FrameNames => false,
+            Md2AnimationPreset::Quake2Player => true,
+        };
+
+        quake2_player.then(|| {
+            QUAKE2_PLAYER_ANIMATIONS
+                .iter()
+                .map(|&(name, first, last)| AnimationRange {
+                    name: name.to_string(),
+                    first,
+                    last,
+                })
+                .collect()
+        })
     }
+}
 
-    // Skins
-    pub fn skins(&self) -> &[Skin] {
-        &self.md2.skins
-    }
+/// How the [`Md2Loader`] searches the model's directory for skins
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SkinSearch {
+    /// Only use the skins listed in the MD2 header
+    Never,
+    /// Search the directory if none of the header skins could be found
+    #[default]
+    Fallback,
+    /// Always add skins found in the directory
+    Always,
+}
+
+/// What the [`Md2Loader`] builds the render mesh from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Md2MeshSource {
+    /// The triangle list with its integer texcoords
+    #[default]
+    Triangles,
+    /// The GL command strips and fans with their float texcoords,
+    /// falling back to the triangles if the model has no GL commands
+    GlCommands,
+}
+
+/// Settings for the [`Md2Loader`]
+///
+/// Fields left out of a `.meta` file keep their defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct Md2LoaderSettings {
+    pub skin_search: SkinSearch,
+    pub preset: Md2AnimationPreset,
+    /// Explicit animation table, overriding the preset
+    pub animations: Option<Vec<AnimationRange>>,
+    pub mesh_source: Md2MeshSource,
+    /// Load 8-bit .pcx skins as palette indices, so [`PlayerColors`] can be applied
+    pub palette_skins: bool,
+    /// Make .pcx skins glow where they use the fullbright entries of the Quake 2 palette
+    ///
+    /// Only useful for skins with the Quake 2 palette, others would glow at random.
+    pub fullbright_skins: bool,
+}
+
+/// Whether a skin image has palette indices, i.e. is an 8-bit PCX
+///
+/// Only the PCX header is looked at.
+fn is_palette_skin(path: &AssetPath, data: &[u8]) -> bool {
+    path.get_full_extension().as_deref() == Some("pcx") && pcx::has_palette_indices(data)
+}
 
-    pub fn skin_name(&self) -> &str {
-        &self.md2.skins[self.skin_idx].name
+/// File name of Quake 2 player models
+const PLAYER_MODEL: &str = "tris.md2";
+/// File name of the weapon model next to a player model
+const WEAPON_MODEL: &str = "weapon.md2";
+
+/// MD2 asset loader
+pub struct Md2Loader {
+    /// Used to search for skins through the model's asset source
+    asset_server: AssetServer,
+}
+
+impl Md2Loader {
+    pub fn new(asset_server: AssetServer) -> Self {
+        Self { asset_server }
     }
 
-    pub fn next_skin(
-        &mut self,
-        asset_server: &Res<AssetServer>,
-        materials: &mut ResMut<Assets<StandardMaterial>>,
-    ) -> MeshMaterial3d<StandardMaterial> {
-        let new_idx = (self.skin_idx + 1) % self.md2.skins.len();
-        self.set_skin_idx(new_idx, asset_server, materials)
+    /// Resolve a skin path from the MD2 header against the model's asset path
+    ///
+    /// Header paths are relative to the game directory, e.g. `models/monsters/tank/skin.pcx`.
+    /// The game directory is found by locating the first component of the skin path in
+    /// the model's path, otherwise the skin is assumed to be next to the model.
+    fn resolve_skin_path(model_path: &AssetPath, skin_path: &Path) -> Option<AssetPath<'static>> {
+        let model_dir = model_path.path().parent()?;
+        let first = skin_path.iter().next()?;
+        let components: Vec<_> = model_dir.iter().collect();
+
+        let path = match components.iter().rposition(|c| *c == first) {
+            Some(idx) => components[..idx]
+                .iter()
+                .collect::<PathBuf>()
+                .join(skin_path),
+            None => model_dir.join(skin_path.file_name()?),
+        };
+
+        Some(AssetPath::from_path_buf(path).with_source(model_path.source().clone_owned()))
     }
 
-    pub fn set_skin_idx(
-        &mut self,
-        idx: usize,
-        asset_server: &Res<AssetServer>,
-        materials: &mut ResMut<Assets<StandardMaterial>>,
-    ) -> MeshMaterial3d<StandardMaterial> {
-        self.skin_idx = idx;
+    /// Find an existing skin image
+    ///
+    /// Skins are often converted between .pcx and .png so both extensions are tried.
+    /// Also returns whether the image has palette indices, i.e. is an 8-bit PCX.
+    /// Only the PCX header is read, so skins don't become dependencies of the model.
+    async fn find_skin_image(
+        &self,
+        path: AssetPath<'static>,
+    ) -> Option<(AssetPath<'static>, bool)> {
+        let mut candidates = vec![path.clone()];
+        for ext in ["pcx", "png"] {
+            let alt = path.path().with_extension(ext);
+            if alt != path.path() {
+                candidates
+                    .push(AssetPath::from_path_buf(alt).with_source(path.source().clone_owned()));
+            }
+        }
 
-        if self.materials[idx].is_none() {
-            let path = AssetPath::from_path_buf(self.md2.skins[idx].path.clone());
-            let texture_handle: Handle<Image> = asset_server.load(path);
-            let mat_handle: Handle<StandardMaterial> = materials.add(StandardMaterial {
-                base_color_texture: Some(texture_handle),
-                unlit: true,
-                ..default()
-            });
+        let source = self.asset_server.get_source(path.source()).ok()?;
+
+        for candidate in candidates {
+            let mut header = Vec::with_capacity(pcx::HDR_BYTES);
+            match source.reader().read(candidate.path()).await {
+                Ok(mut reader) => {
+                    // A short or unreadable header just means the skin isn't indexed
+                    let _ = (&mut reader)
+                        .take(pcx::HDR_BYTES as u64)
+                        .read_to_end(&mut header)
+                        .await;
+                }
+                Err(_) => continue,
+            }
 
-            self.materials[idx] = Some(mat_handle);
+            let indexed = is_palette_skin(&candidate, &header);
+            return Some((candidate, indexed));
         }
 
-        MeshMaterial3d(self.materials[idx].as_ref().unwrap().clone())
+        None
     }
 
-    // Animations
-    pub fn animations(&self) -> &[Animation] {
-        &self.md2.animations
+    /// Whether an asset exists in its asset source
+    ///
+    /// The asset is opened but not read, so it doesn't become a dependency of the model.
+    async fn asset_exists(&self, path: &AssetPath<'_>) -> bool {
+        let Ok(source) = self.asset_server.get_source(path.source()) else {
+            return false;
+        };
+        source.reader().read(path.path()).await.is_ok()
     }
 
-    fn num_anim_frames(&self) -> usize {
-        self.md2.animations[self.anim_idx].key_frames.len()
-    }
+    /// Search the model's directory for skins
+    ///
+    /// Any .pcx or .png image next to the model is treated as a skin.
+    /// The directory is listed through the model's asset source, so PAK
+    /// archives already opened by the source are reused.
+    async fn find_skins(&self, model_path: &AssetPath<'_>) -> Vec<Skin> {
+        let extensions = ["pcx", "png"];
+        let Some(dir) = model_path.path().parent() else {
+            return Vec::new();
+        };
+        let Ok(source) = self.asset_server.get_source(model_path.source()) else {
+            return Vec::new();
+        };
+        let Ok(mut entries) = source.reader().read_directory(dir).await else {
+            return Vec::new();
+        };
+
+        let mut skins = BTreeMap::new();
+
+        while let Some(path) = entries.next().await {
+            let is_image = path
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| extensions.contains(&ext));
+            if !is_image {
+                continue;
+            }
 
-    pub fn next_anim(&mut self) {
-        let next = (self.anim_idx + 1) % self.md2.animations.len();
-        self.set_anim_idx(next);
-    }
+            let name = path.file_stem().unwrap().to_str().unwrap().to_string();
+            let path =
+                AssetPath::from_path_buf(path).with_source(model_path.source().clone_owned());
+            skins.entry(name).or_insert(path);
+        }
 
-    pub fn anim_name(&self) -> &str {
-        &self.md2.animations[self.anim_idx].name
+        skins
+            .into_iter()
+            .map(|(name, path)| Skin {
+                name,
+                path,
+                header_path: None,
+                indexed: false,
+                fullbright: false,
+            })
+            .collect()
     }
+}
 
-    pub fn set_anim_idx(&mut self, idx: usize) {
-        self.anim_idx = idx;
-        self.curr_frame = 0;
-        self.interp = 0.0;
-    }
+impl AssetLoader for Md2Loader {
+    type Asset = Md2Asset;
+    type Settings = Md2LoaderSettings;
+    type Error = Md2LoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        let mut md2 = Md2Asset::from_bytes(&bytes)?;
+        let model_path = load_context.asset_path().clone();
+        let mut skins = Vec::with_capacity(md2.skins.len());
+
+        for skin in &md2.skins {
+            let Some(path) = Md2Loader::resolve_skin_path(&model_path, skin.path.path()) else {
+                continue;
+            };
+
+            match self.find_skin_image(path).await {
+                Some((path, indexed)) => skins.push(Skin {
+                    name: skin.name.clone(),
+                    path,
+                    header_path: skin.header_path.clone(),
+                    indexed: indexed && settings.palette_skins,
+                    fullbright: settings.fullbright_skins,
+                }),
+                None => warn!("Skin {} for {} not found", skin.path, model_path),
+            }
+        }
+
+        let search = match settings.skin_search {
+            SkinSearch::Never => false,
+            SkinSearch::Fallback => skins.is_empty(),
+            SkinSearch::Always => true,
+        };
+
+        if search {
+            for skin in self.find_skins(&model_path).await {
+                if skins.iter().any(|s| s.name == skin.name) {
+                    continue;
+                }
+                if let Some((path, indexed)) = self.find_skin_image(skin.path).await {
+                    skins.push(Skin {
+                        name: skin.name,
+                        path,
+                        header_path: None,
+                        indexed: indexed && settings.palette_skins,
+                        fullbright: settings.fullbright_skins,
+                    });
+                }
+            }
+        }
 
-    pub fn animate(&mut self, delta: f32) -> Vec<Vec3> {
-        let mut interp = self.interp + (8.0f32 * delta);
-        let mut current = self.curr_frame;
-        let mut next = (current + 1) % self.num_anim_frames();
+        md2.skins = skins;
 
-        if interp >= 1.0f32 {
-            current = next;
-            next = (current + 1) % self.num_anim_frames();
-            interp = 0.0f32;
+        // Player models animate a weapon model in lockstep
+        if model_path.path().file_name() == Some(OsStr::new(PLAYER_MODEL)) {
+            let weapon_path =
+                AssetPath::from_path_buf(model_path.path().with_file_name(WEAPON_MODEL))
+                    .with_source(model_path.source().clone_owned());
+            if self.asset_exists(&weapon_path).await {
+                md2.weapon = Some(load_context.load(weapon_path));
+            }
         }
-        self.interp = interp;
-        self.curr_frame = current;
 
-        let curr_v = &self.md2.animations[self.anim_idx].key_frames[current];
-        let next_v = &self.md2.animations[self.anim_idx].key_frames[next];
-        let mut v = Vec::with_capacity(curr_v.len());
+        if settings.mesh_source == Md2MeshSource::GlCommands && md2.use_gl_commands().is_err() {
+            warn!("{} has no GL commands, using its triangles", model_path);
+        }
 
-        for i in 0..curr_v.len() {
-            v.push(curr_v[i].lerp(next_v[i], interp));
+        let table = settings
+            .animations
+            .clone()
+            .or_else(|| settings.preset.table(md2.num_key_frames()));
+        if let Some(table) = table {
+            md2.set_animation_table(&table)?;
         }
 
-        v
+        Ok(md2)
     }
 
-    fn create_mesh(&self) -> Mesh {
-        Mesh::new(
-            PrimitiveTopology::TriangleList,
-            RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
-        )
-        .with_inserted_attribute(
-            Mesh::ATTRIBUTE_POSITION,
-            self.md2.animations[self.anim_idx].key_frames[self.curr_frame].clone(),
-        )
-        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, self.md2.texcoords.clone())
+    fn extensions(&self) -> &[&str] {
+        &["md2"]
     }
 }
 
-/// Resource for available MD2 models
+/// MD2 model instance
 ///
-/// Tracks which model is currently selected.
-#[derive(Resource)]
-pub struct MD2Resource {
-    fpaths: Vec<PathBuf>,
+/// The [`MD2Component`] is attached once the asset has finished loading.
+#[derive(Component)]
+pub struct Md2Model(pub Handle<Md2Asset>);
+
+/// Weapon held by the parent player model
+///
+/// Spawned as a child of instances whose asset has a [`Md2Asset::weapon`],
+/// its playback and skin follow the parent.
+#[derive(Component)]
+pub struct Md2Weapon;
+
+/// Materials for MD2 skins
+///
+/// Instances showing the same skin share a material.
+#[derive(Resource, Default)]
+pub struct Md2SkinMaterials {
+    materials: HashMap<(AssetPath<'static>, Option<PlayerColors>), Handle<Md2Material>>,
+    /// Materials waiting for their skin to pick an alpha mode
+    alpha_pending: Vec<PendingAlpha>,
+    /// Material for models without skins
+    untextured: Option<Handle<Md2Material>>,
+}
+
+/// Material whose alpha mode depends on a skin that is still loading
+struct PendingAlpha {
+    texture: AssetId<Image>,
+    /// Palette of palette skins, which holds the alpha of their pixels
+    palette: Option<AssetId<Image>>,
+    material: Handle<Md2Material>,
+}
+
+impl Md2SkinMaterials {
+    /// Get the material for a skin, creating it on first use
+    ///
+    /// Player colors only apply to palette skins and are ignored otherwise.
+    /// New materials are opaque until the skin has loaded, see
+    /// [`md2_skin_alpha_system`].
+    pub fn get_or_add(
+        &mut self,
+        skin: &Skin,
+        colors: Option<PlayerColors>,
+        asset_server: &AssetServer,
+        materials: &mut Assets<Md2Material>,
+    ) -> Handle<Md2Material> {
+        let colors = colors.filter(|_| skin.indexed);
+        let key = (skin.path.clone(), colors);
+        if let Some(material) = self.materials.get(&key) {
+            return material.clone();
+        }
+
+        let mut base = StandardMaterial::default();
+        let mut extension = Md2MorphExtension::default();
+        let (texture, palette) = if skin.is_pcx() {
+            // Every handle needs the settings, a label alone would load the root without them
+            let (indexed, fullbright) = (skin.indexed, skin.fullbright);
+            let settings = move |s: &mut PcxLoaderSettings| {
+                s.indexed = indexed;
+                s.fullbright = fullbright;
+            };
+            let texture = asset_server.load_with_settings(skin.path.clone(), settings);
+
+            // Quake 2 skins glow where they use the fullbright colors
+            if fullbright {
+                base.emissive = LinearRgba::WHITE;
+                base.emissive_texture =
+                    Some(asset_server.load_with_settings(
+                        skin.path.clone().with_label(FULLBRIGHT_LABEL),
+                        settings,
+                    ));
+            }
+
+            if indexed {
+                let palette = asset_server
+                    .load_with_settings(skin.path.clone().with_label(PALETTE_LABEL), settings);
+                extension = Md2MorphExtension::palette(texture.clone(), palette.clone(), colors);
+                (texture, Some(palette))
+            } else {
+                base.base_color_texture = Some(texture.clone());
+                (texture, None)
+            }
+        } else {
+            let texture: Handle<Image> = asset_server.load(skin.path.clone());
+            base.base_color_texture = Some(texture.clone());
+            (texture, None)
+        };
+
+        let material = materials.add(Md2Material { base, extension });
+        self.alpha_pending.push(PendingAlpha {
+            texture: texture.id(),
+            palette: palette.map(|p| p.id()),
+            material: material.clone(),
+        });
+        self.materials.insert(key, material.clone());
+        material
+    }
+
+    /// Get the material for models without any skins
+    pub fn untextured(&mut self, materials: &mut Assets<Md2Material>) -> Handle<Md2Material> {
+        self.untextured
+            .get_or_insert_with(|| materials.add(Md2Material::default()))
+            .clone()
+    }
+}
+
+/// Alpha mode for a skin image
+///
+/// Skins with fully transparent pixels are masked and ones with partially
+/// transparent pixels are blended.
+fn skin_alpha_mode(image: &Image) -> AlphaMode {
+    match rgba8_data(image) {
+        Some(data) => alpha_mode(data.iter().skip(3).step_by(4).copied()),
+        None => AlphaMode::Opaque,
+    }
+}
+
+/// Alpha mode for a palette skin
+///
+/// Only the palette entries used by the skin's pixels count, as the Quake 2
+/// palette has a transparent entry 255 that most skins never use.
+fn indexed_skin_alpha_mode(indices: &Image, palette: &Image) -> AlphaMode {
+    let indices = indices
+        .data
+        .as_ref()
+        .filter(|_| indices.texture_descriptor.format == TextureFormat::R8Unorm);
+    let (Some(indices), Some(palette)) = (indices, rgba8_data(palette)) else {
+        return AlphaMode::Opaque;
+    };
+
+    alpha_mode(
+        indices
+            .iter()
+            .map(|&i| palette.get(i as usize * 4 + 3).copied().unwrap_or(255)),
+    )
+}
+
+fn rgba8_data(image: &Image) -> Option<&[u8]> {
+    let rgba8 = matches!(
+        image.texture_descriptor.format,
+        TextureFormat::Rgba8Unorm | TextureFormat::Rgba8UnormSrgb
+    );
+    image.data.as_deref().filter(|_| rgba8)
+}
+
+fn alpha_mode(alphas: impl Iterator<Item = u8>) -> AlphaMode {
+    let mut alpha_mode = AlphaMode::Opaque;
+    for alpha in alphas {
+        match alpha {
+            255 => {}
+            0 => alpha_mode = AlphaMode::Mask(0.5),
+            _ => return AlphaMode::Blend,
+        }
+    }
+    alpha_mode
+}
+
+/// Key frame rate of the original Quake 2 animations
+pub const DEFAULT_FPS: f32 = 10.0;
+
+/// What happens when an animation reaches its last key frame
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Start again from the first key frame
+    #[default]
+    Loop,
+    /// Stop and hold the last key frame
+    Once,
+    /// Play backwards to the first key frame, then forwards again
+    PingPong,
+}
+
+/// Triggered when an animation in [`LoopMode::Once`] reaches its last key frame
+#[derive(EntityEvent, Debug, Clone)]
+pub struct AnimationFinished {
+    pub entity: Entity,
+    pub animation: String,
+}
+
+/// Triggered when an animation starts over from its first key frame
+#[derive(EntityEvent, Debug, Clone)]
+pub struct AnimationLooped {
+    pub entity: Entity,
+    pub animation: String,
+}
+
+/// Triggered each time playback advances onto a key frame
+#[derive(EntityEvent, Debug, Clone)]
+pub struct FrameReached {
+    pub entity: Entity,
+    /// Original frame name, see [`KeyFrame::name`]
+    pub name: String,
+    /// Index of the key frame in the animation
+    pub index: usize,
+}
+
+/// Playback event recorded by [`MD2Component::animate`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlaybackEvent {
+    FrameReached(usize),
+    Looped,
+    Finished,
+}
+
+/// Default duration of cross-fades between animations, in seconds
+pub const DEFAULT_FADE_DURATION: f32 = 0.2;
+
+/// Cross-fade from a snapshot pose into the current animation
+#[derive(Debug, Clone)]
+struct Fade {
+    from: KeyFrame,
+    elapsed: f32,
+    duration: f32,
+}
+
+impl Fade {
+    fn weight(&self) -> f32 {
+        (self.elapsed / self.duration).min(1.0)
+    }
+}
+
+/// MD2 Bevy Component
+///
+/// Playback state of an [`Md2Model`] instance, the model data
+/// is shared through the [`Md2Asset`] handle. Allows changing the
+/// current animation and skin.
+#[derive(Component, Clone)]
+pub struct MD2Component {
+    pub skin_idx: usize,
+    pub anim_idx: usize,
+    /// Key frames per second
+    pub fps: f32,
+    pub paused: bool,
+    /// Cross-fade duration used by [`MD2Component::fade_to_anim`], in seconds
+    pub fade_duration: f32,
+    loop_mode: LoopMode,
+    curr_frame: usize,
+    interp: f32,
+    /// Playing backwards in [`LoopMode::PingPong`]
+    reverse: bool,
+    /// Set when the key frames changed without advancing the animation
+    key_frames_changed: bool,
+    /// Events to trigger after the last [`MD2Component::animate`]
+    events: Vec<PlaybackEvent>,
+    fade: Option<Fade>,
+    player_colors: Option<PlayerColors>,
+    material: Handle<Md2Material>,
+}
+
+impl MD2Component {
+    fn new(md2: &Md2Asset) -> Self {
+        let skin_idx = match md2.skins.len() {
+            0 => 0,
+            len => rand::rng().random_range(0..len),
+        };
+        let anim_idx = rand::rng().random_range(0..md2.animations.len());
+
+        Self {
+            skin_idx,
+            anim_idx,
+            fps: DEFAULT_FPS,
+            paused: false,
+            fade_duration: DEFAULT_FADE_DURATION,
+            loop_mode: LoopMode::default(),
+            curr_frame: 0,
+            interp: 0.0,
+            reverse: false,
+            key_frames_changed: false,
+            events: Vec::new(),
+            fade: None,
+            player_colors: None,
+            material: Handle::default(),
+        }
+    }
+
+    // Skins
+    pub fn skin_name<'a>(&self, md2: &'a Md2Asset) -> &'a str {
+        md2.skins
+            .get(self.skin_idx)
+            .map_or("", |skin| skin.name.as_str())
+    }
+
+    pub fn next_skin(
+        &mut self,
+        md2: &Md2Asset,
+        skin_materials: &mut Md2SkinMaterials,
+        asset_server: &AssetServer,
+        materials: &mut Assets<Md2Material>,
+    ) -> MeshMaterial3d<Md2Material> {
+        let new_idx = (self.skin_idx + 1) % md2.skins.len().max(1);
+        self.set_skin_idx(new_idx, md2, skin_materials, asset_server, materials)
+    }
+
+    pub fn set_skin_idx(
+        &mut self,
+        idx: usize,
+        md2: &Md2Asset,
+        skin_materials: &mut Md2SkinMaterials,
+        asset_server: &AssetServer,
+        materials: &mut Assets<Md2Material>,
+    ) -> MeshMaterial3d<Md2Material> {
+        self.skin_idx = idx;
+        // Models without skins get an untextured material
+        self.material = match md2.skins.get(idx) {
+            Some(skin) => {
+                skin_materials.get_or_add(skin, self.player_colors, asset_server, materials)
+            }
+            None => skin_materials.untextured(materials),
+        };
+        MeshMaterial3d(self.material.clone())
+    }
+
+    pub fn player_colors(&self) -> Option<PlayerColors> {
+        self.player_colors
+    }
+
+    /// Remap the shirt and pants colors of palette skins
+    pub fn set_player_colors(
+        &mut self,
+        colors: Option<PlayerColors>,
+        md2: &Md2Asset,
+        skin_materials: &mut Md2SkinMaterials,
+        asset_server: &AssetServer,
+        materials: &mut Assets<Md2Material>,
+    ) -> MeshMaterial3d<Md2Material> {
+        self.player_colors = colors;
+        self.set_skin_idx(self.skin_idx, md2, skin_materials, asset_server, materials)
+    }
+
+    // Animations
+    pub fn num_anim_frames(&self, md2: &Md2Asset) -> usize {
+        md2.animations[self.anim_idx].key_frames.len()
+    }
+
+    pub fn next_anim(&mut self, md2: &Md2Asset) {
+        let next = (self.anim_idx + 1) % md2.animations.len();
+        self.fade_to_anim(md2, next);
+    }
+
+    pub fn anim_name<'a>(&self, md2: &'a Md2Asset) -> &'a str {
+        &md2.animations[self.anim_idx].name
+    }
+
+    pub fn set_anim_idx(&mut self, idx: usize) {
+        self.anim_idx = idx;
+        self.curr_frame = 0;
+        self.interp = 0.0;
+        self.reverse = false;
+        self.fade = None;
+        self.key_frames_changed = true;
+    }
+
+    /// Switch animation, blending from the current pose over [`MD2Component::fade_duration`]
+    ///
+    /// While paused the fade couldn't progress, so this switches right away.
+    pub fn fade_to_anim(&mut self, md2: &Md2Asset, idx: usize) {
+        let from = self.pose(md2);
+        self.set_anim_idx(idx);
+
+        if self.fade_duration > 0.0 && !self.paused {
+            self.fade = Some(Fade {
+                from,
+                elapsed: 0.0,
+                duration: self.fade_duration,
+            });
+        }
+    }
+
+    /// Interpolated pose of the current animation
+    fn anim_pose(&self, md2: &Md2Asset) -> KeyFrame {
+        let (curr, next) = self.key_frames(md2);
+        curr.lerp(next, self.interp)
+    }
+
+    /// Displayed pose, including any cross-fade
+    fn pose(&self, md2: &Md2Asset) -> KeyFrame {
+        let pose = self.anim_pose(md2);
+        match &self.fade {
+            Some(fade) => fade.from.lerp(&pose, fade.weight()),
+            None => pose,
+        }
+    }
+
+    pub fn loop_mode(&self) -> LoopMode {
+        self.loop_mode
+    }
+
+    pub fn set_loop_mode(&mut self, loop_mode: LoopMode) {
+        self.loop_mode = loop_mode;
+        self.key_frames_changed = true;
+    }
+
+    /// Original name of the current key frame
+    pub fn frame_name<'a>(&self, md2: &'a Md2Asset) -> &'a str {
+        &md2.animations[self.anim_idx].key_frames[self.curr_frame].name
+    }
+
+    /// Index of the current key frame in the animation
+    pub fn curr_frame(&self) -> usize {
+        self.curr_frame
+    }
+
+    /// Key frame following the current one, according to the [`LoopMode`]
+    fn next_frame(&self, num_frames: usize) -> usize {
+        let last = num_frames - 1;
+        let curr = self.curr_frame;
+
+        match self.loop_mode {
+            LoopMode::Loop => (curr + 1) % num_frames,
+            LoopMode::Once => (curr + 1).min(last),
+            LoopMode::PingPong => {
+                let reverse = if curr == last {
+                    true
+                } else if curr == 0 {
+                    false
+                } else {
+                    self.reverse
+                };
+
+                if reverse {
+                    curr.saturating_sub(1)
+                } else {
+                    (curr + 1).min(last)
+                }
+            }
+        }
+    }
+
+    /// Advance the animation by `delta` seconds
+    ///
+    /// Several key frames are advanced when `delta` spans more than one,
+    /// and the fractional progress is carried over to the next call.
+    ///
+    /// Returns true if the current or next key frame changed and
+    /// the mesh needs to be updated with [`MD2Component::update_mesh`].
+    pub fn animate(&mut self, md2: &Md2Asset, delta: f32) -> bool {
+        let mut changed = std::mem::take(&mut self.key_frames_changed);
+        if self.paused {
+            // A fade would hide the paused pose and the step controls
+            return self.fade.take().is_some() || changed;
+        }
+
+        // The blended pose is written to the mesh while fading
+        if let Some(fade) = &mut self.fade {
+            fade.elapsed += delta;
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+            changed = true;
+        }
+
+        let num_frames = self.num_anim_frames(md2);
+        let mut interp = self.interp + (self.fps * delta);
+
+        while interp >= 1.0f32 {
+            let next = self.next_frame(num_frames);
+            if next == self.curr_frame {
+                break;
+            }
+
+            let looped = match self.loop_mode {
+                LoopMode::Loop => next < self.curr_frame,
+                LoopMode::Once => false,
+                LoopMode::PingPong => next == 0,
+            };
+            if looped {
+                self.events.push(PlaybackEvent::Looped);
+            }
+
+            self.reverse = next < self.curr_frame;
+            self.curr_frame = next;
+            interp -= 1.0f32;
+            changed = true;
+
+            self.events.push(PlaybackEvent::FrameReached(next));
+            if self.next_frame(num_frames) == next {
+                self.events.push(PlaybackEvent::Finished);
+            }
+        }
+
+        // Hold the last key frame once the animation has finished
+        if self.next_frame(num_frames) == self.curr_frame {
+            interp = 0.0f32;
+        }
+        self.interp = interp;
+
+        changed
+    }
+
+    /// Copy the playback state of `leader`, returning true if the mesh needs updating
+    ///
+    /// Animations are matched by index, so both models need the same animation layout.
+    pub fn follow(&mut self, md2: &Md2Asset, leader: &MD2Component) -> bool {
+        let mut changed = std::mem::take(&mut self.key_frames_changed);
+
+        let anim_idx = leader.anim_idx.min(md2.animations.len() - 1);
+        if anim_idx != self.anim_idx || (leader.fade.is_some() && self.fade.is_none()) {
+            match &leader.fade {
+                Some(fade) => {
+                    self.fade_duration = fade.duration;
+                    self.fade_to_anim(md2, anim_idx);
+                }
+                None => self.set_anim_idx(anim_idx),
+            }
+            self.key_frames_changed = false;
+            changed = true;
+        }
+
+        match (&mut self.fade, &leader.fade) {
+            (Some(fade), Some(leader_fade)) => {
+                fade.elapsed = leader_fade.elapsed;
+                changed = true;
+            }
+            (Some(_), None) => {
+                self.fade = None;
+                changed = true;
+            }
+            _ => {}
+        }
+
+        let curr_frame = leader.curr_frame.min(self.num_anim_frames(md2) - 1);
+        changed |= curr_frame != self.curr_frame
+            || leader.reverse != self.reverse
+            || leader.loop_mode != self.loop_mode;
+
+        self.curr_frame = curr_frame;
+        self.interp = leader.interp;
+        self.reverse = leader.reverse;
+        self.loop_mode = leader.loop_mode;
+        self.fps = leader.fps;
+        self.paused = leader.paused;
+
+        changed
+    }
+
+    /// Step one key frame forward or back, wrapping around the animation
+    pub fn step(&mut self, md2: &Md2Asset, forward: bool) {
+        let num_frames = self.num_anim_frames(md2);
+        let frame = if forward {
+            (self.curr_frame + 1) % num_frames
+        } else {
+            (self.curr_frame + num_frames - 1) % num_frames
+        };
+        self.seek(md2, frame, 0.0);
+    }
+
+    /// Jump to `frame` of the current animation, `interp` of the way to the next key frame
+    pub fn seek(&mut self, md2: &Md2Asset, frame: usize, interp: f32) {
+        self.curr_frame = frame.min(self.num_anim_frames(md2) - 1);
+        self.interp = interp.clamp(0.0, 1.0);
+        self.fade = None;
+        self.key_frames_changed = true;
+    }
+
+    /// Interpolation factor between the current and next key frame
+    pub fn interp(&self) -> f32 {
+        self.interp
+    }
+
+    /// Pass the interpolation factor to the vertex shader
+    ///
+    /// While cross-fading this is the weight of the target animation instead.
+    pub fn mesh_tag(&self) -> MeshTag {
+        let interp = match &self.fade {
+            Some(fade) => fade.weight(),
+            None => self.interp,
+        };
+        MeshTag(interp.to_bits())
+    }
+
+    /// Current and next key frames
+    fn key_frames<'a>(&self, md2: &'a Md2Asset) -> (&'a KeyFrame, &'a KeyFrame) {
+        let key_frames = &md2.animations[self.anim_idx].key_frames;
+        let next = self.next_frame(key_frames.len());
+        (&key_frames[self.curr_frame], &key_frames[next])
+    }
+
+    /// Write the current and next key frames to the mesh
+    ///
+    /// The vertex shader of the [`Md2Material`] blends between them. While
+    /// cross-fading they are the snapshot pose and the target animation's pose.
+    pub fn update_mesh(&self, md2: &Md2Asset, mesh: &mut Mesh) {
+        let target;
+        let (curr, next) = match &self.fade {
+            Some(fade) => {
+                target = self.anim_pose(md2);
+                (&fade.from, &target)
+            }
+            None => self.key_frames(md2),
+        };
+        let layout = &md2.layout;
+
+        mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, layout.gather(&curr.positions));
+        mesh.insert_attribute(Mesh::ATTRIBUTE_NORMAL, layout.gather(&curr.normals));
+        mesh.insert_attribute(ATTRIBUTE_NEXT_POSITION, layout.gather(&next.positions));
+        mesh.insert_attribute(ATTRIBUTE_NEXT_NORMAL, layout.gather(&next.normals));
+    }
+
+    fn create_mesh(&self, md2: &Md2Asset) -> Mesh {
+        let mut mesh = Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::MAIN_WORLD | RenderAssetUsages::RENDER_WORLD,
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, md2.layout.texcoords.clone())
+        .with_inserted_indices(md2.layout.mesh_indices());
+
+        self.update_mesh(md2, &mut mesh);
+        mesh
+    }
+}
+
+/// Resource for available MD2 models
+///
+/// Tracks which model is currently selected, there is no selection
+/// if no models were found.
+#[derive(Resource)]
+pub struct MD2Resource {
+    fpaths: Vec<AssetPath<'static>>,
     pub names: Vec<String>,
-    pub curr_idx: usize,
+    pub curr_idx: Option<usize>,
 }
 
 impl MD2Resource {
     pub fn load(dpath: &Path) -> Self {
-        let fpaths = find_md2(dpath);
+        let mut fpaths = find_md2(dpath);
+        fpaths.extend(find_pak_md2(dpath));
         let names = fpaths
             .iter()
-            .map(|p| MD2Resource::get_model_name(p.as_path()))
+            .map(|p| MD2Resource::get_model_name(p.path()))
             .collect();
-        let curr_idx = rand::rng().random_range(0..fpaths.len());
+        let curr_idx = (!fpaths.is_empty()).then(|| rand::rng().random_range(0..fpaths.len()));
 
         MD2Resource {
             fpaths,
@@ -551,59 +1999,1096 @@ impl MD2Resource {
         }
     }
 
-    pub fn curr_path(&self) -> &Path {
-        &self.fpaths[self.curr_idx]
+    pub fn curr_path(&self) -> Option<AssetPath<'static>> {
+        self.curr_idx.map(|idx| self.fpaths[idx].clone())
     }
 
     pub fn curr_name(&self) -> &str {
-        &self.names[self.curr_idx]
+        self.curr_idx.map_or("", |idx| self.names[idx].as_str())
     }
 
+    /// Name of the model's directory, or its file stem if it has none
     fn get_model_name(fpath: &Path) -> String {
-        let model = fpath
+        fpath
             .parent()
-            .unwrap()
-            .file_name()
-            .unwrap()
-            .to_str()
-            .unwrap();
-        model.to_string()
-    }
-}
-
-/// Spawn a new MD2 instance
-pub fn spawn_md2(
-    path: &Path,
-    commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
-    materials: &mut ResMut<Assets<StandardMaterial>>,
-    meshes: &mut ResMut<Assets<Mesh>>,
-) {
-    let mut md2 = MD2Component::load(path);
-    let mat3d = md2.set_skin_idx(md2.skin_idx, asset_server, materials);
-    let mesh_handle: Handle<Mesh> = meshes.add(md2.create_mesh());
+            .and_then(Path::file_name)
+            .or_else(|| fpath.file_stem())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Spawn a new MD2 instance at `translation`
+///
+/// The mesh and material are created by [`md2_init_system`]
+/// once the asset has finished loading.
+pub fn spawn_md2(handle: Handle<Md2Asset>, translation: Vec3, commands: &mut Commands) -> Entity {
     let scale = 1.0_f32 / 32.0_f32;
     let neg90 = f32::to_radians(-90.0);
+    let name = handle
+        .path()
+        .map(|path| MD2Resource::get_model_name(path.path()))
+        .unwrap_or_default();
+
+    commands
+        .spawn((
+            Md2Model(handle),
+            Name::new(name),
+            Transform::from_translation(translation)
+                .with_rotation(Quat::from_euler(EulerRot::ZYX, 0.0, neg90, 0.0))
+                .with_scale(Vec3::splat(scale)),
+        ))
+        .id()
+}
 
-    commands.spawn((
-        Mesh3d(mesh_handle),
-        mat3d,
-        Transform::from_rotation(Quat::from_euler(EulerRot::ZYX, 0.0, neg90, 0.0))
-            .with_scale(Vec3::splat(scale)),
-        md2,
-    ));
+/// Attach an [`MD2Component`], mesh and material to loaded [`Md2Model`] entities
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn md2_init_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    md2_assets: Res<Assets<Md2Asset>>,
+    mut skin_materials: ResMut<Md2SkinMaterials>,
+    mut materials: ResMut<Assets<Md2Material>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    query: Query<(Entity, &Md2Model, Option<&ChildOf>, Has<Md2Weapon>), Without<MD2Component>>,
+    parents: Query<(&Md2Model, &MD2Component)>,
+) {
+    for (entity, model, child_of, is_weapon) in &query {
+        let Some(asset) = md2_assets.get(&model.0) else {
+            continue;
+        };
+
+        let mut md2 = MD2Component::new(asset);
+
+        // Weapons wait for the player model to pick a matching skin and animation
+        if let Some(child_of) = child_of
+            && is_weapon
+        {
+            let Ok((parent_model, parent)) = parents.get(child_of.parent()) else {
+                continue;
+            };
+            let Some(parent_asset) = md2_assets.get(&parent_model.0) else {
+                continue;
+            };
+
+            md2.skin_idx = asset.matching_skin(parent_asset, parent.skin_idx);
+            md2.player_colors = parent.player_colors;
+            md2.follow(asset, parent);
+        }
+
+        let mat3d = md2.set_skin_idx(
+            md2.skin_idx,
+            asset,
+            &mut skin_materials,
+            &asset_server,
+            &mut materials,
+        );
+        let mesh_handle: Handle<Mesh> = meshes.add(md2.create_mesh(asset));
+
+        commands
+            .entity(entity)
+            .insert((Mesh3d(mesh_handle), mat3d, md2.mesh_tag(), md2));
+
+        if let Some(weapon) = asset.weapon() {
+            commands.entity(entity).with_child((
+                Md2Model(weapon.clone()),
+                Md2Weapon,
+                Name::new("weapon"),
+            ));
+        }
+    }
+}
+
+/// Advance all MD2 animations and trigger their playback events
+pub fn md2_animation_system(
+    mut commands: Commands,
+    time: Res<Time>,
+    md2_assets: Res<Assets<Md2Asset>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut query: Query<
+        (Entity, &Md2Model, &Mesh3d, &mut MeshTag, &mut MD2Component),
+        Without<Md2Weapon>,
+    >,
+) {
+    for (entity, model, mesh, mut tag, mut md2) in &mut query {
+        let Some(asset) = md2_assets.get(&model.0) else {
+            continue;
+        };
+
+        // The mesh is only rewritten when the key frames change,
+        // blending between them happens in the vertex shader.
+        if md2.animate(asset, time.delta_secs()) {
+            let m = meshes.get_mut(mesh.id()).unwrap();
+            md2.update_mesh(asset, m);
+        }
+        *tag = md2.mesh_tag();
+
+        let animation = &asset.animations[md2.anim_idx];
+        for event in md2.events.drain(..) {
+            match event {
+                PlaybackEvent::FrameReached(index) => commands.trigger(FrameReached {
+                    entity,
+                    name: animation.key_frames[index].name.clone(),
+                    index,
+                }),
+                PlaybackEvent::Looped => commands.trigger(AnimationLooped {
+                    entity,
+                    animation: animation.name.clone(),
+                }),
+                PlaybackEvent::Finished => commands.trigger(AnimationFinished {
+                    entity,
+                    animation: animation.name.clone(),
+                }),
+            }
+        }
+    }
+}
+
+/// Keep weapons in step with the player model holding them
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn md2_weapon_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    md2_assets: Res<Assets<Md2Asset>>,
+    mut skin_materials: ResMut<Md2SkinMaterials>,
+    mut materials: ResMut<Assets<Md2Material>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut weapons: Query<
+        (
+            Entity,
+            &ChildOf,
+            &Md2Model,
+            &Mesh3d,
+            &mut MeshTag,
+            &mut MD2Component,
+        ),
+        With<Md2Weapon>,
+    >,
+    parents: Query<(&Md2Model, &MD2Component), Without<Md2Weapon>>,
+) {
+    for (entity, child_of, model, mesh, mut tag, mut md2) in &mut weapons {
+        let Ok((parent_model, parent)) = parents.get(child_of.parent()) else {
+            continue;
+        };
+        let (Some(asset), Some(parent_asset)) =
+            (md2_assets.get(&model.0), md2_assets.get(&parent_model.0))
+        else {
+            continue;
+        };
+
+        let skin_idx = asset.matching_skin(parent_asset, parent.skin_idx);
+        if skin_idx != md2.skin_idx || parent.player_colors != md2.player_colors {
+            md2.player_colors = parent.player_colors;
+            let new_mat = md2.set_skin_idx(
+                skin_idx,
+                asset,
+                &mut skin_materials,
+                &asset_server,
+                &mut materials,
+            );
+            commands.entity(entity).insert(new_mat);
+        }
+
+        if md2.follow(asset, parent) {
+            let m = meshes.get_mut(mesh.id()).unwrap();
+            md2.update_mesh(asset, m);
+        }
+        *tag = md2.mesh_tag();
+    }
+}
+
+/// Switch skin materials to the alpha mode of their skin once it has loaded
+pub fn md2_skin_alpha_system(
+    mut skin_materials: ResMut<Md2SkinMaterials>,
+    images: Res<Assets<Image>>,
+    mut materials: ResMut<Assets<Md2Material>>,
+) {
+    skin_materials.alpha_pending.retain(|pending| {
+        let Some(texture) = images.get(pending.texture) else {
+            return true;
+        };
+        let alpha_mode = match pending.palette {
+            Some(palette) => match images.get(palette) {
+                Some(palette) => indexed_skin_alpha_mode(texture, palette),
+                None => return true,
+            },
+            None => skin_alpha_mode(texture),
+        };
+        if let Some(material) = materials.get_mut(&pending.material) {
+            material.base.alpha_mode = alpha_mode;
+        }
+        false
+    });
+}
+
+/// Rebuild MD2 instances whose asset was hot-reloaded
+pub fn md2_reload_system(
+    mut commands: Commands,
+    mut events: MessageReader<AssetEvent<Md2Asset>>,
+    query: Query<(Entity, &Md2Model), With<MD2Component>>,
+    weapons: Query<(Entity, &ChildOf), With<Md2Weapon>>,
+) {
+    for event in events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+
+        for (entity, model) in &query {
+            if model.0.id() != *id {
+                continue;
+            }
+
+            commands.entity(entity).remove::<MD2Component>();
+
+            // The weapon is spawned again with the player model
+            for (weapon, child_of) in &weapons {
+                if child_of.parent() == entity {
+                    commands.entity(weapon).despawn();
+                }
+            }
+        }
+    }
+}
+
+/// Plugin to register the MD2 asset, loader, material and state machine
+///
+/// This must be added after `DefaultPlugins`, it needs their `AssetServer`.
+pub struct Md2Plugin;
+
+impl Plugin for Md2Plugin {
+    fn build(&self, app: &mut App) {
+        material::add_shaders(app);
+        state_machine::build(app);
+        let asset_server = app.world().resource::<AssetServer>().clone();
+        app.add_plugins(MaterialPlugin::<Md2Material>::default())
+            .init_asset::<Md2Asset>()
+            .init_resource::<Md2SkinMaterials>()
+            .register_asset_loader(Md2Loader::new(asset_server))
+            .add_systems(
+                Update,
+                (
+                    md2_reload_system,
+                    md2_init_system,
+                    state_machine::md2_state_machine_system,
+                    md2_animation_system,
+                    md2_weapon_system,
+                )
+                    .chain(),
+            )
+            .add_systems(Update, md2_skin_alpha_system);
+    }
 }
 
 /// Find all .md2 files on disk
-fn find_md2(assets_path: &Path) -> Vec<PathBuf> {
+///
+/// Returned paths are relative to `assets_path`.
+fn find_md2(assets_path: &Path) -> Vec<AssetPath<'static>> {
     let glob_path = assets_path.join("**").join("*.md2");
     let pattern = glob_path.to_str().unwrap();
     let mut paths = Vec::new();
 
     for entry in glob(pattern).unwrap().filter_map(Result::ok) {
-        let path = entry.to_path_buf();
-        paths.push(path);
+        // Weapons are spawned along with their player model
+        if entry.file_name() == Some(OsStr::new(WEAPON_MODEL)) {
+            continue;
+        }
+
+        let path = entry.strip_prefix(assets_path).unwrap().to_path_buf();
+        paths.push(AssetPath::from_path_buf(path));
     }
 
     paths
 }
+
+/// Find all .md2 files in PAK archives
+///
+/// Returned paths use the `pak://` asset source.
+fn find_pak_md2(assets_path: &Path) -> Vec<AssetPath<'static>> {
+    let mut paths = Vec::new();
+
+    for game in pak::find_game_dirs(assets_path) {
+        let Ok(paks) = pak::open_game_paks(&assets_path.join(&game)) else {
+            continue;
+        };
+
+        let mut names: Vec<&str> = paks
+            .iter()
+            .flat_map(|pak| pak.names())
+            .filter(|name| name.ends_with(".md2") && !name.ends_with(WEAPON_MODEL))
+            .collect();
+        names.sort();
+        names.dedup();
+
+        for name in names {
+            paths.push(AssetPath::from(format!(
+                "{}://{}/{}",
+                PAK_SOURCE, game, name
+            )));
+        }
+    }
+
+    paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Model with a single animation of `num_frames` empty key frames
+    pub(super) fn test_asset(num_frames: usize) -> Md2Asset {
+        let key_frame = KeyFrame {
+            name: "test".to_string(),
+            positions: Vec::new(),
+            normals: Vec::new(),
+        };
+
+        Md2Asset {
+            animations: vec![Animation {
+                name: "test".to_string(),
+                key_frames: vec![key_frame; num_frames],
+            }],
+            layout: MeshLayout {
+                vertex_map: Vec::new(),
+                texcoords: Vec::new(),
+                indices: Vec::new(),
+            },
+            skins: vec![Skin {
+                name: "skin".to_string(),
+                path: AssetPath::from("skin.pcx"),
+                header_path: Some("skin.pcx".to_string()),
+                indexed: false,
+                fullbright: false,
+            }],
+            gl_commands: Vec::new(),
+            skin_size: UVec2::new(64, 64),
+            weapon: None,
+        }
+    }
+
+    /// Run `deltas` through `animate`, returning whether any call changed the key frames
+    fn drive(md2: &mut MD2Component, asset: &Md2Asset, deltas: &[f32]) -> bool {
+        let mut changed = false;
+        for &delta in deltas {
+            changed |= md2.animate(asset, delta);
+        }
+        changed
+    }
+
+    fn assert_playback(md2: &MD2Component, frame: usize, interp: f32) {
+        assert_eq!(md2.curr_frame(), frame);
+        assert!(
+            (md2.interp() - interp).abs() < 1e-4,
+            "interp {} != {}",
+            md2.interp(),
+            interp
+        );
+    }
+
+    fn named_key_frames(names: &[&str]) -> Vec<KeyFrame> {
+        names
+            .iter()
+            .map(|name| KeyFrame {
+                name: name.to_string(),
+                positions: Vec::new(),
+                normals: Vec::new(),
+            })
+            .collect()
+    }
+
+    fn animation_lengths(animations: &[Animation]) -> Vec<(&str, usize)> {
+        animations
+            .iter()
+            .map(|anim| (anim.name.as_str(), anim.key_frames.len()))
+            .collect()
+    }
+
+    /// Smallest valid MD2 file, a single triangle with one key frame
+    fn minimal_md2() -> Vec<u8> {
+        let framesize = (std::mem::size_of::<Frame>() + 3 * std::mem::size_of::<Vertex>()) as i32;
+        let offset_st = HDR_BYTES as i32;
+        let offset_tris = offset_st + 3 * 4;
+        let offset_frames = offset_tris + 12;
+        let offset_end = offset_frames + framesize;
+
+        let mut data = MD2_IDENT.to_vec();
+        #[rustfmt::skip]
+        let fields = [
+            MD2_VERSION, 64, 64, framesize,
+            0, 3, 3, 1, 0, 1,
+            offset_st, offset_st, offset_tris, offset_frames, offset_end, offset_end,
+        ];
+        for field in fields {
+            data.extend_from_slice(&field.to_le_bytes());
+        }
+
+        // Texcoords
+        for st in [0i16, 0, 32, 0, 0, 32] {
+            data.extend_from_slice(&st.to_le_bytes());
+        }
+        // Triangle vertex and texcoord indices
+        for index in [0u16, 1, 2, 0, 1, 2] {
+            data.extend_from_slice(&index.to_le_bytes());
+        }
+        // Frame scale, translate and name, then the vertices
+        for value in [1.0f32, 1.0, 1.0, 0.0, 0.0, 0.0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(b"frame01\0\0\0\0\0\0\0\0\0");
+        data.extend_from_slice(&[0, 0, 0, 0, 10, 0, 0, 0, 0, 10, 0, 0]);
+
+        data
+    }
+
+    /// Overwrite the header field at `idx`, counting the magic as field 0
+    fn set_header_field(data: &mut [u8], idx: usize, value: i32) {
+        data[idx * 4..idx * 4 + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn parse_error(data: &[u8]) -> Md2LoaderError {
+        Md2Asset::from_bytes(data).unwrap_err()
+    }
+
+    #[test]
+    fn parse_minimal_model() {
+        let md2 = Md2Asset::from_bytes(&minimal_md2()).unwrap();
+        assert_eq!(md2.num_key_frames(), 1);
+        assert_eq!(md2.layout().indices, [0, 1, 2]);
+        assert_eq!(md2.skin_size(), UVec2::new(64, 64));
+    }
+
+    #[test]
+    fn header_too_short() {
+        let data = minimal_md2();
+        assert!(matches!(
+            parse_error(&data[..HDR_BYTES - 1]),
+            Md2LoaderError::InvalidFormat(_)
+        ));
+    }
+
+    #[test]
+    fn header_bad_magic() {
+        let mut data = minimal_md2();
+        data[..4].copy_from_slice(b"IDP3");
+        assert!(matches!(
+            parse_error(&data),
+            Md2LoaderError::BadMagic(magic) if magic == *b"IDP3"
+        ));
+    }
+
+    #[test]
+    fn header_unsupported_version() {
+        let mut data = minimal_md2();
+        set_header_field(&mut data, 1, 7);
+        assert!(matches!(
+            parse_error(&data),
+            Md2LoaderError::UnsupportedVersion(7)
+        ));
+    }
+
+    #[test]
+    fn header_section_out_of_bounds() {
+        // Two triangles starting at the last 12 bytes run past the end of the file
+        let mut data = minimal_md2();
+        let offset_tris = data.len() as i32 - 12;
+        set_header_field(&mut data, 13, offset_tris);
+        set_header_field(&mut data, 8, 2);
+        assert!(matches!(
+            parse_error(&data),
+            Md2LoaderError::SectionOutOfBounds {
+                section: "triangles",
+                count: 2,
+                ..
+            }
+        ));
+
+        // Truncated in the middle of the last section
+        let data = minimal_md2();
+        let file_len = data.len() - 1;
+        assert!(matches!(
+            parse_error(&data[..file_len]),
+            Md2LoaderError::SectionOutOfBounds {
+                section: "frames",
+                file_len: len,
+                ..
+            } if len == file_len
+        ));
+    }
+
+    #[test]
+    fn triangle_index_out_of_bounds() {
+        let offset_tris = HDR_BYTES + 3 * 4;
+
+        let mut data = minimal_md2();
+        data[offset_tris + 2..offset_tris + 4].copy_from_slice(&3u16.to_le_bytes());
+        assert!(matches!(
+            parse_error(&data),
+            Md2LoaderError::IndexOutOfBounds {
+                triangle: 0,
+                kind: "vertex",
+                index: 3,
+                count: 3,
+            }
+        ));
+
+        let mut data = minimal_md2();
+        data[offset_tris + 10..offset_tris + 12].copy_from_slice(&5u16.to_le_bytes());
+        assert!(matches!(
+            parse_error(&data),
+            Md2LoaderError::IndexOutOfBounds {
+                triangle: 0,
+                kind: "texcoord",
+                index: 5,
+                count: 3,
+            }
+        ));
+    }
+
+    #[test]
+    fn no_models_found() {
+        let dir = std::env::temp_dir().join(format!("md2-bevy-empty-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let md2s = MD2Resource::load(&dir);
+        std::fs::remove_dir(&dir).unwrap();
+
+        assert!(md2s.names.is_empty());
+        assert_eq!(md2s.curr_idx, None);
+        assert_eq!(md2s.curr_path(), None);
+        assert_eq!(md2s.curr_name(), "");
+    }
+
+    #[test]
+    fn model_names() {
+        let name = |path: &str| MD2Resource::get_model_name(Path::new(path));
+        assert_eq!(name("models/monsters/tank/tris.md2"), "tank");
+        assert_eq!(name("baseq2/players/male/tris.md2"), "male");
+        // Models directly under the assets directory have no parent to name them
+        assert_eq!(name("tris.md2"), "tris");
+    }
+
+    #[test]
+    fn split_frame_names() {
+        assert_eq!(split_frame_name("stand01", 2), ("stand", Some(1)));
+        assert_eq!(split_frame_name("run3", 2), ("run", Some(3)));
+        assert_eq!(split_frame_name("pain201", 2), ("pain2", Some(1)));
+        assert_eq!(split_frame_name("pain201", 3), ("pain", Some(201)));
+        assert_eq!(split_frame_name("crouch_05", 2), ("crouch", Some(5)));
+        assert_eq!(split_frame_name("frame", 2), ("frame", None));
+    }
+
+    #[test]
+    fn group_by_name_and_numbering() {
+        let key_frames = named_key_frames(&[
+            "stand01", "stand02", "pain101", "pain102", "pain201", "pain202", "pain203", "run1",
+            "run2", "run1", "run2",
+        ]);
+
+        let animations = group_key_frames(key_frames);
+        assert_eq!(
+            animation_lengths(&animations),
+            [
+                ("stand", 2),
+                ("pain1", 2),
+                ("pain2", 3),
+                ("run", 2),
+                ("run_2", 2)
+            ]
+        );
+        assert_eq!(animations[2].key_frames[0].name, "pain201");
+    }
+
+    #[test]
+    fn group_three_digit_numbering() {
+        let numbered = |name: &str, range: std::ops::Range<u32>| -> Vec<String> {
+            range.map(|i| format!("{}{:03}", name, i)).collect()
+        };
+        let mut names = numbered("frame", 0..198);
+        names.extend(numbered("stand", 1..41));
+        names.extend(["run1", "run2", "run10", "run11"].map(String::from));
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+
+        let animations = group_key_frames(named_key_frames(&names));
+        assert_eq!(
+            animation_lengths(&animations),
+            [("frame", 198), ("stand", 40), ("run", 2), ("run1", 2)]
+        );
+    }
+
+    #[test]
+    fn animation_table_overrides_grouping() {
+        let mut asset = test_asset(6);
+        let table = [
+            AnimationRange {
+                name: "first".to_string(),
+                first: 0,
+                last: 1,
+            },
+            AnimationRange {
+                name: "second".to_string(),
+                first: 2,
+                last: 5,
+            },
+        ];
+
+        asset.set_animation_table(&table).unwrap();
+        assert_eq!(
+            animation_lengths(&asset.animations),
+            [("first", 2), ("second", 4)]
+        );
+
+        let out_of_range = [AnimationRange {
+            name: "bad".to_string(),
+            first: 4,
+            last: 6,
+        }];
+        assert!(asset.set_animation_table(&out_of_range).is_err());
+    }
+
+    #[test]
+    fn partial_loader_settings() {
+        let settings: Md2LoaderSettings = ron::from_str("(mesh_source: GlCommands)").unwrap();
+        assert_eq!(settings.mesh_source, Md2MeshSource::GlCommands);
+        assert_eq!(settings.skin_search, SkinSearch::Fallback);
+        assert!(settings.animations.is_none());
+        assert!(!settings.fullbright_skins);
+    }
+
+    #[test]
+    fn quake2_player_preset() {
+        let table = Md2AnimationPreset::Quake2Player.table(0).unwrap();
+        for pair in table.windows(2) {
+            assert_eq!(pair[0].last + 1, pair[1].first);
+        }
+        assert_eq!(table.last().unwrap().last + 1, QUAKE2_PLAYER_FRAMES);
+
+        assert!(
+            Md2AnimationPreset::Auto
+                .table(QUAKE2_PLAYER_FRAMES)
+                .is_some()
+        );
+        assert!(Md2AnimationPreset::Auto.table(40).is_none());
+        assert!(
+            Md2AnimationPreset::FrameNames
+                .table(QUAKE2_PLAYER_FRAMES)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn small_deltas_accumulate() {
+        let asset = test_asset(3);
+        let mut md2 = MD2Component::new(&asset);
+
+        assert!(!drive(&mut md2, &asset, &[0.02, 0.03, 0.04]));
+        assert_playback(&md2, 0, 0.9);
+
+        assert!(drive(&mut md2, &asset, &[0.02]));
+        assert_playback(&md2, 1, 0.1);
+    }
+
+    #[test]
+    fn large_delta_advances_several_frames() {
+        let asset = test_asset(5);
+        let mut md2 = MD2Component::new(&asset);
+
+        assert!(md2.animate(&asset, 0.25));
+        assert_playback(&md2, 2, 0.5);
+    }
+
+    #[test]
+    fn remainder_carries_over() {
+        let asset = test_asset(5);
+        let mut md2 = MD2Component::new(&asset);
+
+        // 0.15 + 0.15 frames of 0.1s are exactly three frames
+        drive(&mut md2, &asset, &[0.15, 0.15]);
+        assert_playback(&md2, 3, 0.0);
+    }
+
+    #[test]
+    fn wall_time_independent_of_frame_rate() {
+        let asset = test_asset(7);
+        let mut slow = MD2Component::new(&asset);
+        let mut fast = MD2Component::new(&asset);
+
+        drive(&mut slow, &asset, &[0.4; 3]);
+        drive(&mut fast, &asset, &[0.01; 120]);
+
+        assert_playback(&slow, 5, 0.0);
+        assert_playback(&fast, slow.curr_frame(), slow.interp());
+    }
+
+    #[test]
+    fn loop_wraps_around() {
+        let asset = test_asset(3);
+        let mut md2 = MD2Component::new(&asset);
+
+        md2.animate(&asset, 0.35);
+        assert_playback(&md2, 0, 0.5);
+    }
+
+    #[test]
+    fn once_holds_last_frame() {
+        let asset = test_asset(3);
+        let mut md2 = MD2Component::new(&asset);
+        md2.set_loop_mode(LoopMode::Once);
+
+        assert!(md2.animate(&asset, 1.0));
+        assert_playback(&md2, 2, 0.0);
+
+        assert!(!md2.animate(&asset, 1.0));
+        assert_playback(&md2, 2, 0.0);
+    }
+
+    #[test]
+    fn ping_pong_reverses() {
+        let asset = test_asset(3);
+        let mut md2 = MD2Component::new(&asset);
+        md2.set_loop_mode(LoopMode::PingPong);
+
+        md2.animate(&asset, 0.35);
+        assert_playback(&md2, 1, 0.5);
+
+        md2.animate(&asset, 0.1);
+        assert_playback(&md2, 0, 0.5);
+
+        md2.animate(&asset, 0.1);
+        assert_playback(&md2, 1, 0.5);
+    }
+
+    #[test]
+    fn fps_scales_playback() {
+        let asset = test_asset(10);
+        let mut md2 = MD2Component::new(&asset);
+        md2.fps = 20.0;
+
+        md2.animate(&asset, 0.125);
+        assert_playback(&md2, 2, 0.5);
+    }
+
+    #[test]
+    fn playback_events() {
+        let asset = test_asset(3);
+        let mut md2 = MD2Component::new(&asset);
+
+        md2.animate(&asset, 0.35);
+        let events: Vec<_> = md2.events.drain(..).collect();
+        assert_eq!(
+            events,
+            [
+                PlaybackEvent::FrameReached(1),
+                PlaybackEvent::FrameReached(2),
+                PlaybackEvent::Looped,
+                PlaybackEvent::FrameReached(0),
+            ]
+        );
+
+        md2.set_anim_idx(0);
+        md2.set_loop_mode(LoopMode::Once);
+        md2.animate(&asset, 1.0);
+        let events: Vec<_> = md2.events.drain(..).collect();
+        assert_eq!(
+            events,
+            [
+                PlaybackEvent::FrameReached(1),
+                PlaybackEvent::FrameReached(2),
+                PlaybackEvent::Finished,
+            ]
+        );
+    }
+
+    #[test]
+    fn fade_blends_into_target() {
+        let mut asset = test_asset(3);
+        asset.animations.push(asset.animations[0].clone());
+        let mut md2 = MD2Component::new(&asset);
+        md2.set_anim_idx(0);
+        md2.fade_duration = 0.2;
+
+        md2.fade_to_anim(&asset, 1);
+        assert_eq!(md2.anim_idx, 1);
+
+        assert!(md2.animate(&asset, 0.05));
+        assert_eq!(md2.mesh_tag(), MeshTag(0.25f32.to_bits()));
+
+        // The key frames are restored once the fade is done
+        assert!(md2.animate(&asset, 0.15));
+        assert_eq!(md2.mesh_tag(), MeshTag(md2.interp().to_bits()));
+        assert_playback(&md2, 2, 0.0);
+    }
+
+    #[test]
+    fn paused_does_not_advance() {
+        let asset = test_asset(3);
+        let mut md2 = MD2Component::new(&asset);
+        md2.paused = true;
+
+        assert!(!drive(&mut md2, &asset, &[0.5, 0.5]));
+        assert_playback(&md2, 0, 0.0);
+    }
+
+    #[test]
+    fn change_animation_while_paused() {
+        let mut asset = test_asset(3);
+        asset.animations.push(asset.animations[0].clone());
+        let mut md2 = MD2Component::new(&asset);
+        md2.set_anim_idx(0);
+        md2.fade_duration = 0.2;
+        md2.paused = true;
+
+        // No fade that would stay stuck on the old pose
+        md2.fade_to_anim(&asset, 1);
+        assert!(md2.animate(&asset, 0.1));
+        assert_eq!(md2.mesh_tag(), MeshTag(md2.interp().to_bits()));
+
+        md2.step(&asset, true);
+        assert!(md2.animate(&asset, 0.1));
+        assert_playback(&md2, 1, 0.0);
+    }
+
+    #[test]
+    fn seek_ends_fade() {
+        let mut asset = test_asset(3);
+        asset.animations.push(asset.animations[0].clone());
+        let mut md2 = MD2Component::new(&asset);
+        md2.set_anim_idx(0);
+        md2.fade_duration = 0.2;
+
+        md2.fade_to_anim(&asset, 1);
+        md2.seek(&asset, 1, 0.5);
+        assert_eq!(md2.mesh_tag(), MeshTag(0.5f32.to_bits()));
+    }
+
+    #[test]
+    fn weapon_follows_player() {
+        let mut asset = test_asset(3);
+        asset.animations.push(asset.animations[0].clone());
+        let mut player = MD2Component::new(&asset);
+        let mut weapon = MD2Component::new(&asset);
+        player.set_anim_idx(0);
+        player.fade_duration = 0.2;
+
+        player.fade_to_anim(&asset, 1);
+        player.animate(&asset, 0.15);
+        assert!(weapon.follow(&asset, &player));
+        assert_eq!(weapon.anim_idx, 1);
+        assert_eq!(weapon.mesh_tag(), player.mesh_tag());
+
+        player.animate(&asset, 0.1);
+        assert!(weapon.follow(&asset, &player));
+        assert_eq!(weapon.mesh_tag(), player.mesh_tag());
+        assert_playback(&weapon, player.curr_frame(), player.interp());
+
+        // Nothing changes while the player holds still
+        player.paused = true;
+        assert!(!weapon.follow(&asset, &player));
+        assert!(weapon.paused);
+    }
+
+    #[test]
+    fn matching_weapon_skin() {
+        let mut player = test_asset(1);
+        let mut weapon = test_asset(1);
+        let skin = |name: &str| Skin {
+            name: name.to_string(),
+            path: AssetPath::from(format!("{}.pcx", name)),
+            header_path: None,
+            indexed: false,
+            fullbright: false,
+        };
+        player.skins = vec![skin("male"), skin("cipher"), skin("razor")];
+        weapon.skins = vec![skin("weapon"), skin("razor")];
+
+        assert_eq!(weapon.matching_skin(&player, 2), 1);
+        assert_eq!(weapon.matching_skin(&player, 1), 1);
+        assert_eq!(weapon.matching_skin(&player, 0), 0);
+    }
+
+    #[test]
+    fn model_without_skins() {
+        let mut player = test_asset(1);
+        player.skins.clear();
+
+        let md2 = MD2Component::new(&player);
+        assert_eq!(md2.skin_idx, 0);
+        assert_eq!(md2.skin_name(&player), "");
+        assert_eq!(test_asset(1).matching_skin(&player, 0), 0);
+    }
+
+    #[test]
+    fn resolve_skin_paths() {
+        fn resolve(model: &str, skin: &str) -> Option<String> {
+            Md2Loader::resolve_skin_path(&AssetPath::parse(model), Path::new(skin))
+                .map(|path| path.to_string())
+        }
+
+        // Header path relative to the game directory
+        assert_eq!(
+            resolve(
+                "models/monsters/tank/tris.md2",
+                "models/monsters/tank/skin.pcx"
+            )
+            .as_deref(),
+            Some("models/monsters/tank/skin.pcx")
+        );
+        assert_eq!(
+            resolve("baseq2/players/female/tris.md2", "players/male/grunt.pcx").as_deref(),
+            Some("baseq2/players/male/grunt.pcx")
+        );
+        assert_eq!(
+            resolve("pak://baseq2/models/tank/tris.md2", "models/tank/skin.pcx").as_deref(),
+            Some("pak://baseq2/models/tank/skin.pcx")
+        );
+
+        // Unrelated paths fall back to the model's directory
+        assert_eq!(
+            resolve("tank/tris.md2", "models/monsters/tank/skin.pcx").as_deref(),
+            Some("tank/skin.pcx")
+        );
+        assert_eq!(resolve("tris.md2", "skin.pcx").as_deref(), Some("skin.pcx"));
+
+        assert_eq!(resolve("tank/tris.md2", ""), None);
+    }
+
+    /// Tetrahedron with a seam on its first vertex and two animations
+    pub(super) fn tetrahedron() -> Md2Asset {
+        let corners = [Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z];
+        let key_frame = |name: &str, t: f32| KeyFrame {
+            name: name.to_string(),
+            positions: corners
+                .iter()
+                .map(|c| c * (10.0 + t) + Vec3::new(t, -t, 0.5 * t))
+                .collect(),
+            normals: corners
+                .iter()
+                .map(|c| (c - Vec3::splat(0.25)).normalize())
+                .collect(),
+        };
+
+        Md2Asset {
+            animations: vec![
+                Animation {
+                    name: "stand".to_string(),
+                    key_frames: vec![key_frame("stand01", 0.0), key_frame("stand02", 0.3)],
+                },
+                Animation {
+                    name: "run".to_string(),
+                    key_frames: vec![
+                        key_frame("run1", 1.0),
+                        key_frame("run2", 2.0),
+                        key_frame("run3", 3.0),
+                    ],
+                },
+            ],
+            layout: MeshLayout {
+                vertex_map: vec![0, 1, 2, 3, 0],
+                texcoords: vec![
+                    Vec2::new(0.0, 0.0),
+                    Vec2::new(0.5, 0.0),
+                    Vec2::new(0.0, 0.5),
+                    Vec2::new(0.5, 0.5),
+                    Vec2::new(1.0, 1.0),
+                ],
+                indices: vec![0, 2, 1, 0, 1, 3, 1, 2, 3, 2, 4, 3],
+            },
+            skins: vec![Skin {
+                name: "skin".to_string(),
+                path: AssetPath::from("baseq2/models/test/skin.png"),
+                header_path: Some("models/test/skin.pcx".to_string()),
+                indexed: false,
+                fullbright: false,
+            }],
+            gl_commands: Vec::new(),
+            skin_size: UVec2::new(64, 32),
+            weapon: None,
+        }
+    }
+
+    #[test]
+    fn gl_command_triangles() {
+        let vertices = (0..5)
+            .map(|index| GlVertex {
+                texcoord: Vec2::ZERO,
+                index,
+            })
+            .collect();
+        let mut command = GlCommand {
+            kind: GlCommandKind::Strip,
+            vertices,
+        };
+        let indices = |command: &GlCommand| -> Vec<[u32; 3]> {
+            command
+                .triangles()
+                .map(|tri| tri.map(|v| v.index))
+                .collect()
+        };
+
+        // Every other strip triangle is flipped to keep the winding
+        assert_eq!(indices(&command), [[0, 1, 2], [2, 1, 3], [2, 3, 4]]);
+
+        command.kind = GlCommandKind::Fan;
+        assert_eq!(indices(&command), [[0, 1, 2], [0, 2, 3], [0, 3, 4]]);
+    }
+
+    #[test]
+    fn only_pcx_skins_are_indexed() {
+        // 8-bit PCX header
+        let mut header = vec![0u8; pcx::HDR_BYTES];
+        header[3] = 8;
+        header[65] = 1;
+
+        assert!(is_palette_skin(&AssetPath::from("skin.pcx"), &header));
+        assert!(!is_palette_skin(&AssetPath::from("skin.png"), &header));
+    }
+
+    #[test]
+    fn skin_alpha_modes() {
+        let skin = |alpha: [u8; 2]| {
+            let data = alpha.iter().flat_map(|&a| [255, 255, 255, a]).collect();
+            Image::new(
+                bevy::render::render_resource::Extent3d {
+                    width: 2,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                bevy::render::render_resource::TextureDimension::D2,
+                data,
+                TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::default(),
+            )
+        };
+
+        assert_eq!(skin_alpha_mode(&skin([255, 255])), AlphaMode::Opaque);
+        assert_eq!(skin_alpha_mode(&skin([0, 255])), AlphaMode::Mask(0.5));
+        assert_eq!(skin_alpha_mode(&skin([0, 128])), AlphaMode::Blend);
+    }
+
+    #[test]
+    fn indexed_skin_alpha_modes() {
+        let image = |width: u32, data: Vec<u8>, format| {
+            Image::new(
+                bevy::render::render_resource::Extent3d {
+                    width,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+                bevy::render::render_resource::TextureDimension::D2,
+                data,
+                format,
+                RenderAssetUsages::default(),
+            )
+        };
+        // Like the Quake 2 palette, only the last entry is transparent
+        let alphas = (0..256).flat_map(|i| [0, 0, 0, if i == 255 { 0 } else { 255 }]);
+        let palette = image(256, alphas.collect(), TextureFormat::Rgba8UnormSrgb);
+        let skin = |indices: [u8; 2]| image(2, indices.to_vec(), TextureFormat::R8Unorm);
+
+        assert_eq!(
+            indexed_skin_alpha_mode(&skin([0, 208]), &palette),
+            AlphaMode::Opaque
+        );
+        assert_eq!(
+            indexed_skin_alpha_mode(&skin([0, 255]), &palette),
+            AlphaMode::Mask(0.5)
+        );
+    }
+}
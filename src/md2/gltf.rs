@@ -0,0 +1,460 @@
+//! glTF 2.0 export of MD2 models
+//!
+//! The first key frame becomes the base mesh and every key frame becomes a
+//! morph target holding its offsets from the base. Each [`Animation`] is
+//! exported as a glTF animation stepping through its key frames on the
+//! morph target weights, so linear interpolation between two weight sets
+//! blends key frames the same way the renderer does.
+use base64::{Engine, prelude::BASE64_STANDARD};
+use bevy::prelude::*;
+use serde_json::{Value, json};
+use std::io::Cursor;
+use std::path::Path;
+use thiserror::Error;
+
+use super::{Animation, DEFAULT_FPS, Md2Asset};
+use crate::pcx::{PcxLoaderError, parse_pcx};
+
+#[derive(Debug, Error)]
+pub enum GltfExportError {
+    #[error("Failed to write glTF file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to encode glTF JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Failed to read skin: {0}")]
+    Pcx(#[from] PcxLoaderError),
+    #[error("Failed to convert skin: {0}")]
+    Image(String),
+    #[error("Unsupported glTF file extension {0:?}, expected .gltf or .glb")]
+    Extension(String),
+}
+
+/// Skin image of the exported material
+#[derive(Debug, Clone, Default)]
+pub enum GltfSkin {
+    /// Export the mesh without a material
+    #[default]
+    None,
+    /// Reference an image file by URI, relative to the glTF file
+    Uri(String),
+    /// Embed PNG data in the glTF buffer
+    Png(Vec<u8>),
+}
+
+/// Options for [`export_gltf`]
+#[derive(Debug, Clone)]
+pub struct GltfOptions {
+    /// Key frames per second of the exported animations
+    pub fps: f32,
+    pub skin: GltfSkin,
+}
+
+impl Default for GltfOptions {
+    fn default() -> Self {
+        Self {
+            fps: DEFAULT_FPS,
+            skin: GltfSkin::None,
+        }
+    }
+}
+
+/// Exported glTF JSON document and its binary buffer
+pub struct GltfDocument {
+    json: Value,
+    buffer: Vec<u8>,
+}
+
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+const TRIANGLES: u32 = 4;
+
+const GLB_MAGIC: u32 = 0x4654_6c67;
+const GLB_VERSION: u32 = 2;
+const GLB_JSON_CHUNK: u32 = 0x4e4f_534a;
+const GLB_BIN_CHUNK: u32 = 0x004e_4942;
+
+/// Builds the binary buffer along with its buffer views and accessors
+#[derive(Default)]
+struct BufferBuilder {
+    buffer: Vec<u8>,
+    views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl BufferBuilder {
+    /// Append a 4 byte aligned buffer view, returning its index
+    fn push_view(&mut self, bytes: &[u8], target: Option<u32>) -> usize {
+        self.buffer.resize(self.buffer.len().next_multiple_of(4), 0);
+
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": bytes.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = target.into();
+        }
+
+        self.buffer.extend_from_slice(bytes);
+        self.views.push(view);
+        self.views.len() - 1
+    }
+
+    /// Append an accessor over a new buffer view, returning its index
+    fn push_accessor(
+        &mut self,
+        bytes: &[u8],
+        target: Option<u32>,
+        component_type: u32,
+        count: usize,
+        kind: &str,
+    ) -> usize {
+        let view = self.push_view(bytes, target);
+        self.accessors.push(json!({
+            "bufferView": view,
+            "componentType": component_type,
+            "count": count,
+            "type": kind,
+        }));
+        self.accessors.len() - 1
+    }
+
+    /// Append VEC3 floats, with the bounds glTF requires for positions
+    fn push_vec3(&mut self, values: &[Vec3], bounds: bool) -> usize {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.to_array())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        let idx = self.push_accessor(&bytes, Some(ARRAY_BUFFER), FLOAT, values.len(), "VEC3");
+
+        if bounds {
+            let min = values.iter().copied().reduce(Vec3::min).unwrap_or_default();
+            let max = values.iter().copied().reduce(Vec3::max).unwrap_or_default();
+            self.accessors[idx]["min"] = json!(min.to_array());
+            self.accessors[idx]["max"] = json!(max.to_array());
+        }
+
+        idx
+    }
+
+    fn push_vec2(&mut self, values: &[Vec2]) -> usize {
+        let bytes: Vec<u8> = values
+            .iter()
+            .flat_map(|v| v.to_array())
+            .flat_map(f32::to_le_bytes)
+            .collect();
+        self.push_accessor(&bytes, Some(ARRAY_BUFFER), FLOAT, values.len(), "VEC2")
+    }
+
+    /// Append triangle indices, using 16 bit indices when possible
+    fn push_indices(&mut self, indices: &[u32], num_vertices: usize) -> usize {
+        if num_vertices <= usize::from(u16::MAX) + 1 {
+            let bytes: Vec<u8> = indices
+                .iter()
+                .flat_map(|&i| (i as u16).to_le_bytes())
+                .collect();
+            self.push_accessor(
+                &bytes,
+                Some(ELEMENT_ARRAY_BUFFER),
+                UNSIGNED_SHORT,
+                indices.len(),
+                "SCALAR",
+            )
+        } else {
+            let bytes: Vec<u8> = indices.iter().flat_map(|&i| i.to_le_bytes()).collect();
+            self.push_accessor(
+                &bytes,
+                Some(ELEMENT_ARRAY_BUFFER),
+                UNSIGNED_INT,
+                indices.len(),
+                "SCALAR",
+            )
+        }
+    }
+
+    /// Append animation sampler floats, inputs also need their bounds
+    fn push_scalars(&mut self, values: &[f32], bounds: bool) -> usize {
+        let bytes: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        let idx = self.push_accessor(&bytes, None, FLOAT, values.len(), "SCALAR");
+
+        if bounds {
+            let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+            let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+            self.accessors[idx]["min"] = json!([min]);
+            self.accessors[idx]["max"] = json!([max]);
+        }
+
+        idx
+    }
+}
+
+/// Export `md2` as a glTF document with a morph target per key frame
+pub fn export_gltf(md2: &Md2Asset, options: &GltfOptions) -> GltfDocument {
+    let layout = md2.layout();
+    let key_frames: Vec<_> = md2
+        .animations()
+        .iter()
+        .flat_map(|anim| &anim.key_frames)
+        .collect();
+    let base = key_frames[0];
+    let base_positions = layout.gather(&base.positions);
+    let base_normals = layout.gather(&base.normals);
+
+    let mut builder = BufferBuilder::default();
+    let attributes = json!({
+        "POSITION": builder.push_vec3(&base_positions, true),
+        "NORMAL": builder.push_vec3(&base_normals, false),
+        "TEXCOORD_0": builder.push_vec2(&layout.texcoords),
+    });
+    let indices = builder.push_indices(&layout.indices, layout.texcoords.len());
+
+    let targets: Vec<Value> = key_frames
+        .iter()
+        .map(|key_frame| {
+            let positions: Vec<Vec3> = layout
+                .gather(&key_frame.positions)
+                .iter()
+                .zip(&base_positions)
+                .map(|(p, base)| p - base)
+                .collect();
+            let normals: Vec<Vec3> = layout
+                .gather(&key_frame.normals)
+                .iter()
+                .zip(&base_normals)
+                .map(|(n, base)| n - base)
+                .collect();
+
+            json!({
+                "POSITION": builder.push_vec3(&positions, true),
+                "NORMAL": builder.push_vec3(&normals, false),
+            })
+        })
+        .collect();
+
+    let mut primitive = json!({
+        "attributes": attributes,
+        "indices": indices,
+        "mode": TRIANGLES,
+        "targets": targets,
+    });
+
+    let mut json = json!({
+        "asset": { "version": "2.0", "generator": "md2-bevy" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "mesh": 0, "name": "md2" }],
+    });
+
+    if let Some(image) = export_skin(&mut builder, &options.skin) {
+        primitive["material"] = 0.into();
+        json["images"] = json!([image]);
+        json["textures"] = json!([{ "source": 0 }]);
+        json["materials"] = json!([{
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": 0 },
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            },
+        }]);
+    }
+
+    let target_names: Vec<&str> = key_frames.iter().map(|kf| kf.name.as_str()).collect();
+    json["meshes"] = json!([{
+        "primitives": [primitive],
+        "weights": vec![0.0; key_frames.len()],
+        "extras": { "targetNames": target_names },
+    }]);
+
+    let mut first = 0;
+    let animations: Vec<Value> = md2
+        .animations()
+        .iter()
+        .map(|anim| {
+            let animation =
+                export_animation(&mut builder, anim, first, key_frames.len(), options.fps);
+            first += anim.key_frames.len();
+            animation
+        })
+        .collect();
+    json["animations"] = animations.into();
+
+    json["buffers"] = json!([{ "byteLength": builder.buffer.len() }]);
+    json["bufferViews"] = builder.views.into();
+    json["accessors"] = builder.accessors.into();
+
+    GltfDocument {
+        json,
+        buffer: builder.buffer,
+    }
+}
+
+/// glTF image for the skin, embedded images are stored in the buffer
+fn export_skin(builder: &mut BufferBuilder, skin: &GltfSkin) -> Option<Value> {
+    match skin {
+        GltfSkin::None => None,
+        GltfSkin::Uri(uri) => Some(json!({ "uri": uri })),
+        GltfSkin::Png(bytes) => Some(json!({
+            "bufferView": builder.push_view(bytes, None),
+            "mimeType": "image/png",
+        })),
+    }
+}
+
+/// Animation stepping the weights through the key frames of `anim`
+///
+/// The first key frame is repeated at the end so looping players wrap
+/// around smoothly.
+fn export_animation(
+    builder: &mut BufferBuilder,
+    anim: &Animation,
+    first: usize,
+    num_targets: usize,
+    fps: f32,
+) -> Value {
+    let num_frames = anim.key_frames.len();
+    let steps = if num_frames > 1 { num_frames + 1 } else { 1 };
+
+    let times: Vec<f32> = (0..steps).map(|step| step as f32 / fps).collect();
+    let mut weights = vec![0.0; steps * num_targets];
+    for step in 0..steps {
+        weights[step * num_targets + first + step % num_frames] = 1.0;
+    }
+
+    let input = builder.push_scalars(&times, true);
+    let output = builder.push_scalars(&weights, false);
+
+    json!({
+        "name": anim.name,
+        "samplers": [{ "input": input, "output": output, "interpolation": "LINEAR" }],
+        "channels": [{ "sampler": 0, "target": { "node": 0, "path": "weights" } }],
+    })
+}
+
+impl GltfDocument {
+    /// JSON of the document
+    pub fn json(&self) -> &Value {
+        &self.json
+    }
+
+    /// `.gltf` text with the buffer embedded as a data URI
+    pub fn to_gltf(&self) -> Result<String, GltfExportError> {
+        let mut json = self.json.clone();
+        json["buffers"][0]["uri"] = format!(
+            "data:application/octet-stream;base64,{}",
+            BASE64_STANDARD.encode(&self.buffer)
+        )
+        .into();
+        Ok(serde_json::to_string_pretty(&json)?)
+    }
+
+    /// Binary `.glb` container
+    pub fn to_glb(&self) -> Result<Vec<u8>, GltfExportError> {
+        let mut json = serde_json::to_vec(&self.json)?;
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut buffer = self.buffer.clone();
+        buffer.resize(buffer.len().next_multiple_of(4), 0);
+
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut glb = Vec::with_capacity(length);
+        for word in [GLB_MAGIC, GLB_VERSION, length as u32] {
+            glb.extend_from_slice(&word.to_le_bytes());
+        }
+        for (chunk_type, chunk) in [(GLB_JSON_CHUNK, &json), (GLB_BIN_CHUNK, &buffer)] {
+            glb.extend_from_slice(&(chunk.len() as u32).to_le_bytes());
+            glb.extend_from_slice(&chunk_type.to_le_bytes());
+            glb.extend_from_slice(chunk);
+        }
+
+        Ok(glb)
+    }
+
+    /// Write a `.gltf` or `.glb` file depending on the extension of `path`
+    pub fn write(&self, path: &Path) -> Result<(), GltfExportError> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gltf") => std::fs::write(path, self.to_gltf()?)?,
+            Some("glb") => std::fs::write(path, self.to_glb()?)?,
+            ext => return Err(GltfExportError::Extension(ext.unwrap_or("").to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// Read a .png or .pcx skin as PNG data
+pub fn skin_png(path: &Path) -> Result<Vec<u8>, GltfExportError> {
+    let bytes = std::fs::read(path)?;
+    if path.extension().is_some_and(|ext| ext == "png") {
+        return Ok(bytes);
+    }
+
+    let image = parse_pcx(&bytes, &default(), None)?
+        .try_into_dynamic()
+        .map_err(|e| GltfExportError::Image(e.to_string()))?;
+    let mut png = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| GltfExportError::Image(e.to_string()))?;
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md2::tests::{test_asset, tetrahedron};
+
+    #[test]
+    fn morph_targets_and_animations() {
+        let mut asset = test_asset(3);
+        asset.animations.push(Animation {
+            name: "other".to_string(),
+            key_frames: asset.animations[0].key_frames[..2].to_vec(),
+        });
+
+        let doc = export_gltf(&asset, &GltfOptions::default());
+        let json = doc.json();
+        let targets = &json["meshes"][0]["primitives"][0]["targets"];
+        assert_eq!(targets.as_array().unwrap().len(), 5);
+
+        // Key frames plus the wrap around, with a weight per target each
+        let accessors = &json["accessors"];
+        let sampler = &json["animations"][1]["samplers"][0];
+        let input = sampler["input"].as_u64().unwrap() as usize;
+        let output = sampler["output"].as_u64().unwrap() as usize;
+        assert_eq!(json["animations"][1]["name"], "other");
+        assert_eq!(accessors[input]["count"], 3);
+        assert_eq!(accessors[output]["count"], 3 * 5);
+    }
+
+    #[test]
+    fn glb_chunk_layout() {
+        let doc = export_gltf(&tetrahedron(), &GltfOptions::default());
+        let glb = doc.to_glb().unwrap();
+        let word = |offset: usize| u32::from_le_bytes(glb[offset..offset + 4].try_into().unwrap());
+
+        assert_eq!(word(0), GLB_MAGIC);
+        assert_eq!(word(4), GLB_VERSION);
+        assert_eq!(word(8) as usize, glb.len());
+
+        // JSON chunk padded with spaces to a multiple of 4 bytes
+        let json_len = word(12) as usize;
+        assert_eq!(word(16), GLB_JSON_CHUNK);
+        assert_eq!(json_len % 4, 0);
+        let json: Value = serde_json::from_slice(&glb[20..20 + json_len]).unwrap();
+        assert_eq!(&json, doc.json());
+
+        // Followed by the BIN chunk holding the whole buffer
+        let bin = 20 + json_len;
+        let bin_len = word(bin) as usize;
+        assert_eq!(word(bin + 4), GLB_BIN_CHUNK);
+        assert_eq!(bin_len % 4, 0);
+        assert_eq!(bin + 8 + bin_len, glb.len());
+        assert_eq!(
+            json["buffers"][0]["byteLength"].as_u64().unwrap() as usize,
+            doc.buffer.len()
+        );
+        assert!(doc.buffer.len() <= bin_len);
+    }
+}
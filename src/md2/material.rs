@@ -0,0 +1,183 @@
+//! Vertex shader key frame interpolation
+//!
+//! The mesh holds the current and next key frames as two sets of vertex
+//! attributes and the vertex shader blends between them. The blend factor
+//! is passed per instance through the [`MeshTag`], so advancing an animation
+//! only touches the mesh when the key frames change.
+//!
+//! Skins loaded as palette indices are colored in the fragment shader, which
+//! can remap the shirt and pants ranges to other [`PlayerColors`].
+use bevy::{
+    asset::embedded_asset,
+    mesh::{MeshVertexAttribute, MeshVertexBufferLayoutRef, VertexFormat},
+    pbr::{ExtendedMaterial, MaterialExtension, MaterialExtensionKey, MaterialExtensionPipeline},
+    prelude::*,
+    render::render_resource::{
+        AsBindGroup, RenderPipelineDescriptor, ShaderType, SpecializedMeshPipelineError,
+    },
+    shader::{ShaderDefVal, ShaderRef},
+};
+use serde::{Deserialize, Serialize};
+
+/// Position in the next key frame
+pub const ATTRIBUTE_NEXT_POSITION: MeshVertexAttribute =
+    MeshVertexAttribute::new("Md2_NextPosition", 0x6d64_3201, VertexFormat::Float32x3);
+
+/// Normal in the next key frame
+pub const ATTRIBUTE_NEXT_NORMAL: MeshVertexAttribute =
+    MeshVertexAttribute::new("Md2_NextNormal", 0x6d64_3202, VertexFormat::Float32x3);
+
+const SHADER_PATH: &str = "embedded://md2_bevy/md2/morph.wgsl";
+const PREPASS_SHADER_PATH: &str = "embedded://md2_bevy/md2/morph_prepass.wgsl";
+const PALETTE_SHADER_PATH: &str = "embedded://md2_bevy/md2/palette.wgsl";
+
+/// Shader locations of the next key frame attributes, past the ones used by bevy
+const NEXT_POSITION_LOCATION: u32 = 8;
+const NEXT_NORMAL_LOCATION: u32 = 9;
+
+/// Standard PBR material with key frame interpolation
+pub type Md2Material = ExtendedMaterial<StandardMaterial, Md2MorphExtension>;
+
+/// Quake player colors, as rows of the 16 color ramps in the palette
+///
+/// The shirt (palette 16-31) and pants (palette 96-111) ranges of palette
+/// skins are replaced with these ramps.
+#[derive(Serialize, Deserialize, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PlayerColors {
+    pub shirt: u8,
+    pub pants: u8,
+}
+
+/// Number of color ramps in the palette
+pub const PALETTE_RAMPS: u8 = 16;
+
+/// [`PlayerColors`] as passed to the shader
+#[derive(ShaderType, Reflect, Debug, Clone, Copy, Default)]
+pub struct ColorTranslation {
+    shirt: u32,
+    pants: u32,
+    /// Non-zero if the colors are remapped
+    enabled: u32,
+}
+
+impl From<Option<PlayerColors>> for ColorTranslation {
+    fn from(colors: Option<PlayerColors>) -> Self {
+        match colors {
+            Some(colors) => Self {
+                shirt: u32::from(colors.shirt % PALETTE_RAMPS),
+                pants: u32::from(colors.pants % PALETTE_RAMPS),
+                enabled: 1,
+            },
+            None => Self::default(),
+        }
+    }
+}
+
+/// [`MaterialExtension`] replacing the vertex shaders to blend key frames
+///
+/// With a palette skin the base color is looked up from the palette
+/// indices instead of the [`StandardMaterial`] texture.
+#[derive(Asset, AsBindGroup, Reflect, Debug, Clone, Default)]
+#[bind_group_data(Md2MorphKey)]
+pub struct Md2MorphExtension {
+    /// Skin loaded with [`PcxLoaderSettings::indexed`](crate::pcx::PcxLoaderSettings::indexed)
+    #[texture(100)]
+    pub palette_indices: Option<Handle<Image>>,
+    /// 256x1 palette of the skin
+    #[texture(101)]
+    pub palette: Option<Handle<Image>>,
+    #[uniform(102)]
+    pub translation: ColorTranslation,
+}
+
+/// Pipeline key of a [`Md2MorphExtension`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Md2MorphKey {
+    palette: bool,
+}
+
+impl From<&Md2MorphExtension> for Md2MorphKey {
+    fn from(extension: &Md2MorphExtension) -> Self {
+        Self {
+            palette: extension.palette_indices.is_some(),
+        }
+    }
+}
+
+impl Md2MorphExtension {
+    /// Palette skin with optional player colors
+    pub fn palette(
+        palette_indices: Handle<Image>,
+        palette: Handle<Image>,
+        colors: Option<PlayerColors>,
+    ) -> Self {
+        Self {
+            palette_indices: Some(palette_indices),
+            palette: Some(palette),
+            translation: colors.into(),
+        }
+    }
+}
+
+impl MaterialExtension for Md2MorphExtension {
+    fn vertex_shader() -> ShaderRef {
+        SHADER_PATH.into()
+    }
+
+    fn fragment_shader() -> ShaderRef {
+        PALETTE_SHADER_PATH.into()
+    }
+
+    fn prepass_vertex_shader() -> ShaderRef {
+        PREPASS_SHADER_PATH.into()
+    }
+
+    fn deferred_vertex_shader() -> ShaderRef {
+        PREPASS_SHADER_PATH.into()
+    }
+
+    fn specialize(
+        _pipeline: &MaterialExtensionPipeline,
+        descriptor: &mut RenderPipelineDescriptor,
+        layout: &MeshVertexBufferLayoutRef,
+        key: MaterialExtensionKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        if key.bind_group_data.palette
+            && let Some(fragment) = descriptor.fragment.as_mut()
+        {
+            fragment.shader_defs.push("MD2_PALETTE".into());
+        }
+
+        // The prepass and main pass use different locations for the standard attributes
+        let prepass = descriptor
+            .vertex
+            .shader_defs
+            .contains(&ShaderDefVal::from("PREPASS_PIPELINE"));
+
+        let mut attributes = if prepass {
+            vec![
+                Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+                Mesh::ATTRIBUTE_UV_0.at_shader_location(1),
+                Mesh::ATTRIBUTE_NORMAL.at_shader_location(3),
+            ]
+        } else {
+            vec![
+                Mesh::ATTRIBUTE_POSITION.at_shader_location(0),
+                Mesh::ATTRIBUTE_NORMAL.at_shader_location(1),
+                Mesh::ATTRIBUTE_UV_0.at_shader_location(2),
+            ]
+        };
+        attributes.push(ATTRIBUTE_NEXT_POSITION.at_shader_location(NEXT_POSITION_LOCATION));
+        attributes.push(ATTRIBUTE_NEXT_NORMAL.at_shader_location(NEXT_NORMAL_LOCATION));
+
+        descriptor.vertex.buffers = vec![layout.0.get_layout(&attributes)?];
+        Ok(())
+    }
+}
+
+/// Embed the key frame interpolation and palette shaders
+pub(crate) fn add_shaders(app: &mut App) {
+    embedded_asset!(app, "morph.wgsl");
+    embedded_asset!(app, "morph_prepass.wgsl");
+    embedded_asset!(app, "palette.wgsl");
+}
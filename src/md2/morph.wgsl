@@ -0,0 +1,46 @@
+// Main pass vertex shader blending between two MD2 key frames.
+//
+// The interpolation factor is stored as f32 bits in the instance's MeshTag.
+#import bevy_pbr::{
+    mesh_functions,
+    forward_io::VertexOutput,
+    view_transformations::position_world_to_clip,
+}
+
+struct Vertex {
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) uv: vec2<f32>,
+    @location(8) next_position: vec3<f32>,
+    @location(9) next_normal: vec3<f32>,
+};
+
+@vertex
+fn vertex(vertex: Vertex) -> VertexOutput {
+    var out: VertexOutput;
+
+    let interp = bitcast<f32>(mesh_functions::get_tag(vertex.instance_index));
+    let position = mix(vertex.position, vertex.next_position, interp);
+    let normal = normalize(mix(vertex.normal, vertex.next_normal, interp));
+
+    let world_from_local = mesh_functions::get_world_from_local(vertex.instance_index);
+    out.world_normal = mesh_functions::mesh_normal_local_to_world(normal, vertex.instance_index);
+    out.world_position = mesh_functions::mesh_position_local_to_world(world_from_local, vec4<f32>(position, 1.0));
+    out.position = position_world_to_clip(out.world_position.xyz);
+
+#ifdef VERTEX_UVS_A
+    out.uv = vertex.uv;
+#endif
+
+#ifdef VERTEX_OUTPUT_INSTANCE_INDEX
+    out.instance_index = vertex.instance_index;
+#endif
+
+#ifdef VISIBILITY_RANGE_DITHER
+    out.visibility_range_dither = mesh_functions::get_visibility_range_dither_level(
+        vertex.instance_index, world_from_local[3]);
+#endif
+
+    return out;
+}
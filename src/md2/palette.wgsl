@@ -0,0 +1,68 @@
+// Fragment shader coloring palette index skins.
+//
+// Without MD2_PALETTE this is the standard PBR fragment shader. With it the
+// base color is looked up from the palette, remapping the shirt and pants
+// ranges to the player colors like Quake's R_TranslatePlayerSkin.
+#import bevy_pbr::{
+    pbr_fragment::pbr_input_from_standard_material,
+    pbr_functions::{alpha_discard, apply_pbr_lighting, main_pass_post_lighting_processing},
+    forward_io::{VertexOutput, FragmentOutput},
+}
+
+struct ColorTranslation {
+    shirt: u32,
+    pants: u32,
+    enabled: u32,
+};
+
+@group(#{MATERIAL_BIND_GROUP}) @binding(100) var palette_indices: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(101) var palette: texture_2d<f32>;
+@group(#{MATERIAL_BIND_GROUP}) @binding(102) var<uniform> translation: ColorTranslation;
+
+const SHIRT_RANGE: u32 = 16u;
+const PANTS_RANGE: u32 = 96u;
+const RAMP_COLORS: u32 = 16u;
+
+// Palette entry of `index` in the ramp of `row`, the upper ramps run from bright to dark
+fn translate_range(index: u32, range: u32, row: u32) -> u32 {
+    let offset = index - range;
+    if row < 8u {
+        return row * RAMP_COLORS + offset;
+    }
+    return row * RAMP_COLORS + RAMP_COLORS - 1u - offset;
+}
+
+fn translate(index: u32) -> u32 {
+    if translation.enabled == 0u {
+        return index;
+    }
+    if index >= SHIRT_RANGE && index < SHIRT_RANGE + RAMP_COLORS {
+        return translate_range(index, SHIRT_RANGE, translation.shirt);
+    }
+    if index >= PANTS_RANGE && index < PANTS_RANGE + RAMP_COLORS {
+        return translate_range(index, PANTS_RANGE, translation.pants);
+    }
+    return index;
+}
+
+@fragment
+fn fragment(in: VertexOutput, @builtin(front_facing) is_front: bool) -> FragmentOutput {
+    var pbr_input = pbr_input_from_standard_material(in, is_front);
+
+#ifdef MD2_PALETTE
+#ifdef VERTEX_UVS_A
+    // Indices are fetched without filtering, blending them would mix unrelated colors
+    let size = vec2<f32>(textureDimensions(palette_indices));
+    let texel = vec2<u32>(clamp(floor(in.uv * size), vec2(0.0), size - 1.0));
+    let index = u32(round(textureLoad(palette_indices, texel, 0).r * 255.0));
+    pbr_input.material.base_color *= textureLoad(palette, vec2<u32>(translate(index), 0u), 0);
+#endif
+#endif
+
+    pbr_input.material.base_color = alpha_discard(pbr_input.material, pbr_input.material.base_color);
+
+    var out: FragmentOutput;
+    out.color = apply_pbr_lighting(pbr_input);
+    out.color = main_pass_post_lighting_processing(pbr_input, out.color);
+    return out;
+}
@@ -0,0 +1,451 @@
+//! Data-driven animation state machine for MD2 characters
+//!
+//! A [`Md2StateMachine`] maps named states to MD2 animations and describes
+//! the transitions between them. It is loaded from `.md2states.ron` files:
+//!
+//! ```ron
+//! (
+//!     initial: "stand",
+//!     states: {
+//!         "stand": (animation: "stand", transitions: [(to: "run", when: Param("moving", true))]),
+//!         "run": (animation: "run", transitions: [(to: "stand", when: Param("moving", false))]),
+//!         "attack": (animation: "attack", loop_mode: Once, transitions: [(to: "stand", when: Finished)]),
+//!         "death": (animation: "death", loop_mode: Once),
+//!     },
+//!     any_state: [(to: "death", when: Trigger("die"))],
+//! )
+//! ```
+use bevy::{
+    asset::{AssetLoader, LoadContext},
+    prelude::*,
+};
+use ron::{Options, extensions::Extensions};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+use super::{AnimationFinished, AnimationLooped, LoopMode, MD2Component, Md2Asset, Md2Model};
+
+#[derive(Debug, Error)]
+pub enum Md2StateMachineLoaderError {
+    #[error("Failed to read state machine: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to parse state machine: {0}")]
+    Ron(#[from] ron::de::SpannedError),
+    #[error("Invalid state machine: {0}")]
+    InvalidFormat(String),
+}
+
+/// When a [`Transition`] is taken
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// The state's animation finished or looped
+    Finished,
+    /// A boolean parameter has the given value, unset parameters are false
+    Param(String, bool),
+    /// A trigger was set, it is consumed by the transition
+    Trigger(String),
+}
+
+/// Transition to another state
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Transition {
+    pub to: String,
+    pub when: Condition,
+}
+
+/// State playing a single MD2 animation
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct State {
+    /// Name of the MD2 animation, e.g. "run"
+    pub animation: String,
+    #[serde(default)]
+    pub loop_mode: LoopMode,
+    /// Key frames per second, the instance's rate is kept if unset
+    #[serde(default)]
+    pub fps: Option<f32>,
+    /// Cross-fade duration into this state, the instance's duration is used if unset
+    #[serde(default)]
+    pub fade: Option<f32>,
+    #[serde(default)]
+    pub transitions: Vec<Transition>,
+}
+
+/// Animation state machine asset
+#[derive(Asset, TypePath, Serialize, Deserialize, Debug, Clone)]
+pub struct Md2StateMachine {
+    pub initial: String,
+    pub states: HashMap<String, State>,
+    /// Transitions checked in every state before the state's own
+    #[serde(default)]
+    pub any_state: Vec<Transition>,
+}
+
+impl Md2StateMachine {
+    /// Parse and validate a `.md2states.ron` file
+    pub fn from_bytes(bytes: &[u8]) -> Result<Md2StateMachine, Md2StateMachineLoaderError> {
+        // Optional fields can be written without `Some(..)`
+        let options = Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        let machine: Md2StateMachine = options.from_bytes(bytes)?;
+        machine.validate()?;
+        Ok(machine)
+    }
+
+    /// Ensure the initial state and all transition targets exist
+    fn validate(&self) -> Result<(), Md2StateMachineLoaderError> {
+        let transitions = self
+            .states
+            .values()
+            .flat_map(|state| &state.transitions)
+            .chain(&self.any_state);
+
+        for name in std::iter::once(&self.initial).chain(transitions.map(|t| &t.to)) {
+            if !self.states.contains_key(name) {
+                return Err(Md2StateMachineLoaderError::InvalidFormat(format!(
+                    "Unknown state {}",
+                    name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ensure the animations of all states exist in `md2`
+    ///
+    /// This can only be checked once the model is known, states with
+    /// unknown animations keep playing the previous animation.
+    pub fn validate_animations(&self, md2: &Md2Asset) -> Result<(), Md2StateMachineLoaderError> {
+        for (name, state) in &self.states {
+            if !md2
+                .animations()
+                .iter()
+                .any(|anim| anim.name == state.animation)
+            {
+                return Err(Md2StateMachineLoaderError::InvalidFormat(format!(
+                    "State {} uses unknown animation {}",
+                    name, state.animation
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// First transition out of `current` whose condition holds
+    fn find_transition(
+        &self,
+        current: &str,
+        player: &Md2StateMachinePlayer,
+    ) -> Option<&Transition> {
+        let any_state = self.any_state.iter().filter(|t| t.to != current);
+        let own = self
+            .states
+            .get(current)
+            .into_iter()
+            .flat_map(|state| &state.transitions);
+
+        any_state.chain(own).find(|t| player.holds(&t.when))
+    }
+}
+
+/// Loader for `.md2states.ron` files
+#[derive(Default)]
+pub struct Md2StateMachineLoader;
+
+impl AssetLoader for Md2StateMachineLoader {
+    type Asset = Md2StateMachine;
+    type Settings = ();
+    type Error = Md2StateMachineLoaderError;
+
+    async fn load(
+        &self,
+        reader: &mut dyn bevy::asset::io::Reader,
+        _settings: &Self::Settings,
+        _load_context: &mut LoadContext<'_>,
+    ) -> Result<Self::Asset, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Md2StateMachine::from_bytes(&bytes)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["md2states.ron"]
+    }
+}
+
+/// Drives an [`MD2Component`] with a [`Md2StateMachine`]
+#[derive(Component)]
+pub struct Md2StateMachinePlayer {
+    pub machine: Handle<Md2StateMachine>,
+    state: Option<String>,
+    params: HashMap<String, bool>,
+    triggers: HashSet<String>,
+    /// The current state's animation finished or looped
+    finished: bool,
+}
+
+impl Md2StateMachinePlayer {
+    pub fn new(machine: Handle<Md2StateMachine>) -> Self {
+        Self {
+            machine,
+            state: None,
+            params: HashMap::new(),
+            triggers: HashSet::new(),
+            finished: false,
+        }
+    }
+
+    /// Name of the current state, once the machine has started
+    pub fn state(&self) -> Option<&str> {
+        self.state.as_deref()
+    }
+
+    pub fn param(&self, name: &str) -> bool {
+        self.params.get(name).copied().unwrap_or(false)
+    }
+
+    pub fn set_param(&mut self, name: impl Into<String>, value: bool) {
+        self.params.insert(name.into(), value);
+    }
+
+    /// Set a trigger, it stays set until a transition consumes it
+    pub fn trigger(&mut self, name: impl Into<String>) {
+        self.triggers.insert(name.into());
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::Finished => self.finished,
+            Condition::Param(name, value) => self.param(name) == *value,
+            Condition::Trigger(name) => self.triggers.contains(name),
+        }
+    }
+
+    /// State to enter next, the initial state if the machine hasn't started
+    ///
+    /// A trigger is consumed by the transition it fires.
+    fn next_state<'a>(&mut self, machine: &'a Md2StateMachine) -> Option<&'a str> {
+        let Some(current) = self.state() else {
+            return Some(&machine.initial);
+        };
+
+        let transition = machine.find_transition(current, self)?;
+        if let Condition::Trigger(name) = &transition.when {
+            self.triggers.remove(name);
+        }
+        Some(&transition.to)
+    }
+
+    /// Switch the MD2 instance to the animation of `name`
+    fn enter(&mut self, name: &str, state: &State, md2: &mut MD2Component, asset: &Md2Asset) {
+        self.state = Some(name.to_string());
+        self.finished = false;
+
+        let Some(anim_idx) = asset
+            .animations()
+            .iter()
+            .position(|anim| anim.name == state.animation)
+        else {
+            warn!("State {} uses unknown animation {}", name, state.animation);
+            return;
+        };
+
+        if let Some(fps) = state.fps {
+            md2.fps = fps;
+        }
+        md2.set_loop_mode(state.loop_mode);
+
+        let fade_duration = md2.fade_duration;
+        md2.fade_duration = state.fade.unwrap_or(fade_duration);
+        md2.fade_to_anim(asset, anim_idx);
+        md2.fade_duration = fade_duration;
+    }
+}
+
+/// Start state machines and take transitions whose condition holds
+pub fn md2_state_machine_system(
+    machines: Res<Assets<Md2StateMachine>>,
+    md2_assets: Res<Assets<Md2Asset>>,
+    mut query: Query<(&Md2Model, &mut MD2Component, &mut Md2StateMachinePlayer)>,
+) {
+    for (model, mut md2, mut player) in &mut query {
+        let (Some(machine), Some(asset)) =
+            (machines.get(&player.machine), md2_assets.get(&model.0))
+        else {
+            continue;
+        };
+
+        if player.state().is_none()
+            && let Err(err) = machine.validate_animations(asset)
+        {
+            warn!("{}", err);
+        }
+
+        let Some(next) = player.next_state(machine) else {
+            continue;
+        };
+
+        player.enter(next, &machine.states[next], &mut md2, asset);
+    }
+}
+
+fn on_animation_finished(
+    event: On<AnimationFinished>,
+    mut query: Query<&mut Md2StateMachinePlayer>,
+) {
+    if let Ok(mut player) = query.get_mut(event.entity) {
+        player.finished = true;
+    }
+}
+
+fn on_animation_looped(event: On<AnimationLooped>, mut query: Query<&mut Md2StateMachinePlayer>) {
+    if let Ok(mut player) = query.get_mut(event.entity) {
+        player.finished = true;
+    }
+}
+
+/// Register the state machine asset, loader and observers
+pub(crate) fn build(app: &mut App) {
+    app.init_asset::<Md2StateMachine>()
+        .register_asset_loader(Md2StateMachineLoader)
+        .add_observer(on_animation_finished)
+        .add_observer(on_animation_looped);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md2::{Animation, tests::test_asset};
+
+    const SAMPLE: &str = r#"
+(
+    initial: "stand",
+    states: {
+        "stand": (animation: "stand", transitions: [(to: "run", when: Param("moving", true))]),
+        "run": (animation: "run", fps: 15.0, transitions: [(to: "stand", when: Param("moving", false))]),
+        "attack": (animation: "attack", loop_mode: Once, transitions: [(to: "stand", when: Finished)]),
+        "death": (animation: "death", loop_mode: Once),
+    },
+    any_state: [(to: "death", when: Trigger("die"))],
+)
+"#;
+
+    fn sample() -> Md2StateMachine {
+        Md2StateMachine::from_bytes(SAMPLE.as_bytes()).unwrap()
+    }
+
+    fn player() -> Md2StateMachinePlayer {
+        Md2StateMachinePlayer::new(Handle::default())
+    }
+
+    /// Model with an animation for each name
+    fn animated_asset(names: &[&str]) -> Md2Asset {
+        let mut asset = test_asset(2);
+        let animation = asset.animations[0].clone();
+        asset.animations = names
+            .iter()
+            .map(|name| Animation {
+                name: name.to_string(),
+                ..animation.clone()
+            })
+            .collect();
+        asset
+    }
+
+    #[test]
+    fn parse_sample() {
+        let machine = sample();
+        assert_eq!(machine.initial, "stand");
+        assert_eq!(machine.states.len(), 4);
+
+        let run = &machine.states["run"];
+        assert_eq!(run.animation, "run");
+        assert_eq!(run.fps, Some(15.0));
+        assert_eq!(run.loop_mode, LoopMode::Loop);
+        assert_eq!(machine.states["attack"].loop_mode, LoopMode::Once);
+        assert_eq!(
+            machine.any_state[0].when,
+            Condition::Trigger("die".to_string())
+        );
+    }
+
+    #[test]
+    fn reject_unknown_states() {
+        let unknown_initial = r#"(initial: "idle", states: {"stand": (animation: "stand")})"#;
+        assert!(matches!(
+            Md2StateMachine::from_bytes(unknown_initial.as_bytes()),
+            Err(Md2StateMachineLoaderError::InvalidFormat(_))
+        ));
+
+        let unknown_target = r#"(
+            initial: "stand",
+            states: {"stand": (animation: "stand", transitions: [(to: "run", when: Finished)])},
+        )"#;
+        assert!(matches!(
+            Md2StateMachine::from_bytes(unknown_target.as_bytes()),
+            Err(Md2StateMachineLoaderError::InvalidFormat(_))
+        ));
+
+        assert!(matches!(
+            Md2StateMachine::from_bytes(b"(initial: )"),
+            Err(Md2StateMachineLoaderError::Ron(_))
+        ));
+    }
+
+    #[test]
+    fn reject_unknown_animations() {
+        let machine = sample();
+        let all = animated_asset(&["stand", "run", "attack", "death"]);
+        assert!(machine.validate_animations(&all).is_ok());
+
+        let missing = animated_asset(&["stand", "run", "attack"]);
+        assert!(matches!(
+            machine.validate_animations(&missing),
+            Err(Md2StateMachineLoaderError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn transitions() {
+        let machine = sample();
+        let mut player = player();
+        let to = |player: &Md2StateMachinePlayer, current: &str| {
+            machine
+                .find_transition(current, player)
+                .map(|t| t.to.clone())
+        };
+
+        // Unset parameters are false
+        assert_eq!(to(&player, "stand"), None);
+        player.set_param("moving", true);
+        assert_eq!(to(&player, "stand").as_deref(), Some("run"));
+        assert_eq!(to(&player, "run"), None);
+
+        assert_eq!(to(&player, "attack"), None);
+        player.finished = true;
+        assert_eq!(to(&player, "attack").as_deref(), Some("stand"));
+
+        // Any state transitions come first, but don't re-enter their target
+        player.trigger("die");
+        assert_eq!(to(&player, "run").as_deref(), Some("death"));
+        assert_eq!(to(&player, "death"), None);
+    }
+
+    #[test]
+    fn trigger_is_consumed() {
+        let machine = sample();
+        let mut player = player();
+
+        assert_eq!(player.next_state(&machine), Some("stand"));
+        player.state = Some("stand".to_string());
+        assert_eq!(player.next_state(&machine), None);
+
+        player.trigger("die");
+        assert_eq!(player.next_state(&machine), Some("death"));
+        assert!(player.triggers.is_empty());
+
+        // Without the trigger the state stays put
+        assert_eq!(player.next_state(&machine), None);
+    }
+}
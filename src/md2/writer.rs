@@ -0,0 +1,450 @@
+//! MD2 file writer
+//!
+//! Writes an [`Md2Asset`] back out in the MD2 format. Key frame vertices are
+//! quantized to bytes within the bounds of each frame, normals are snapped
+//! to the nearest precomputed normal and the GL commands are rebuilt from
+//! the triangles.
+use bevy::prelude::*;
+use std::collections::HashMap;
+use thiserror::Error;
+
+use super::anorms::ANORMS;
+use super::{
+    Frame, GlCommand, GlCommandKind, GlVertex, HDR_BYTES, KeyFrame, MD2_IDENT, MD2_VERSION,
+    Md2Asset, MeshLayout, SKIN_NAME_BYTES, TexCoord, Triangle, Vertex,
+};
+
+#[derive(Debug, Error)]
+pub enum Md2WriterError {
+    #[error("Too many {what} for MD2: {count}, at most {max}")]
+    TooMany {
+        what: &'static str,
+        count: usize,
+        max: usize,
+    },
+    #[error("Name {name:?} is longer than {max} bytes")]
+    NameTooLong { name: String, max: usize },
+    #[error("Key frame {name} has {count} vertices, expected {expected}")]
+    VertexCount {
+        name: String,
+        count: usize,
+        expected: usize,
+    },
+}
+
+/// Size of a frame name, including the terminating NUL
+const FRAME_NAME_BYTES: usize = 16;
+
+/// Largest value of a quantized vertex coordinate
+const VERTEX_STEPS: f32 = 255.0;
+
+/// Serialize `md2` to the contents of an MD2 file
+///
+/// The key frames of all animations are written in order, so a model
+/// whose frames were dropped by an animation table loses them here too.
+/// Skins are written with their path from the MD2 header, skins found by
+/// searching the model's directory are left out.
+pub fn write_md2(md2: &Md2Asset) -> Result<Vec<u8>, Md2WriterError> {
+    let layout = md2.layout();
+    let key_frames: Vec<&KeyFrame> = md2
+        .animations()
+        .iter()
+        .flat_map(|anim| &anim.key_frames)
+        .collect();
+
+    let num_xyz = key_frames[0].positions.len();
+    check_count("vertices", num_xyz, usize::from(u16::MAX) + 1)?;
+    for key_frame in &key_frames {
+        for count in [key_frame.positions.len(), key_frame.normals.len()] {
+            if count != num_xyz {
+                return Err(Md2WriterError::VertexCount {
+                    name: key_frame.name.clone(),
+                    count,
+                    expected: num_xyz,
+                });
+            }
+        }
+    }
+
+    // Texcoords are stored in skin pixels, mesh vertices on the same pixel share one
+    let skin_size = md2.skin_size().as_vec2();
+    let mut texcoords = Vec::new();
+    let mut texcoord_index = HashMap::new();
+    let mesh_st: Vec<u16> = layout
+        .texcoords
+        .iter()
+        .map(|uv| {
+            let st = (uv * skin_size).round();
+            let key = (st.x as i16, st.y as i16);
+            *texcoord_index.entry(key).or_insert_with(|| {
+                texcoords.push(key);
+                (texcoords.len() - 1) as u16
+            })
+        })
+        .collect();
+    check_count("texcoords", texcoords.len(), usize::from(u16::MAX) + 1)?;
+
+    let triangles: Vec<Triangle> = layout
+        .indices
+        .chunks_exact(3)
+        .map(|tri| Triangle {
+            vertex: [0, 1, 2].map(|i| layout.vertex_map[tri[i] as usize] as u16),
+            st: [0, 1, 2].map(|i| mesh_st[tri[i] as usize]),
+        })
+        .collect();
+
+    let mut glcmds: Vec<i32> = Vec::new();
+    for command in build_gl_commands(layout) {
+        let count = command.vertices.len() as i32;
+        glcmds.push(match command.kind {
+            GlCommandKind::Strip => count,
+            GlCommandKind::Fan => -count,
+        });
+        for vertex in command.vertices {
+            glcmds.push(vertex.texcoord.x.to_bits() as i32);
+            glcmds.push(vertex.texcoord.y.to_bits() as i32);
+            glcmds.push(vertex.index as i32);
+        }
+    }
+    glcmds.push(0);
+
+    let skins: Vec<&str> = md2
+        .skins()
+        .iter()
+        .filter_map(|skin| skin.header_path.as_deref())
+        .collect();
+    let framesize = std::mem::size_of::<Frame>() + num_xyz * std::mem::size_of::<Vertex>();
+    let offset_skins = HDR_BYTES;
+    let offset_st = offset_skins + skins.len() * SKIN_NAME_BYTES;
+    let offset_tris = offset_st + texcoords.len() * std::mem::size_of::<TexCoord>();
+    let offset_frames = offset_tris + triangles.len() * std::mem::size_of::<Triangle>();
+    let offset_glcmd = offset_frames + key_frames.len() * framesize;
+    let offset_end = offset_glcmd + glcmds.len() * 4;
+
+    let mut out = Vec::with_capacity(offset_end);
+    out.extend_from_slice(&MD2_IDENT);
+    let fields = [
+        MD2_VERSION as usize,
+        md2.skin_size().x as usize,
+        md2.skin_size().y as usize,
+        framesize,
+        skins.len(),
+        num_xyz,
+        texcoords.len(),
+        triangles.len(),
+        glcmds.len(),
+        key_frames.len(),
+        offset_skins,
+        offset_st,
+        offset_tris,
+        offset_frames,
+        offset_glcmd,
+        offset_end,
+    ];
+    for field in fields {
+        out.extend_from_slice(&(field as i32).to_le_bytes());
+    }
+
+    for path in skins {
+        write_name(&mut out, path, SKIN_NAME_BYTES)?;
+    }
+
+    for (s, t) in texcoords {
+        out.extend_from_slice(&s.to_le_bytes());
+        out.extend_from_slice(&t.to_le_bytes());
+    }
+
+    for triangle in &triangles {
+        for index in triangle.vertex.iter().chain(&triangle.st) {
+            out.extend_from_slice(&index.to_le_bytes());
+        }
+    }
+
+    for key_frame in &key_frames {
+        write_frame(&mut out, key_frame)?;
+    }
+
+    for word in glcmds {
+        out.extend_from_slice(&word.to_le_bytes());
+    }
+
+    Ok(out)
+}
+
+fn check_count(what: &'static str, count: usize, max: usize) -> Result<(), Md2WriterError> {
+    if count > max {
+        return Err(Md2WriterError::TooMany { what, count, max });
+    }
+    Ok(())
+}
+
+/// Write a NUL terminated name padded to `len` bytes
+fn write_name(out: &mut Vec<u8>, name: &str, len: usize) -> Result<(), Md2WriterError> {
+    if name.len() >= len {
+        return Err(Md2WriterError::NameTooLong {
+            name: name.to_string(),
+            max: len - 1,
+        });
+    }
+
+    out.extend_from_slice(name.as_bytes());
+    out.resize(out.len() + len - name.len(), 0);
+    Ok(())
+}
+
+/// Write a frame header and its quantized vertices
+fn write_frame(out: &mut Vec<u8>, key_frame: &KeyFrame) -> Result<(), Md2WriterError> {
+    // Undo the swap of y and z done when loading
+    let positions: Vec<Vec3> = key_frame.positions.iter().map(|p| p.xzy()).collect();
+    let min = positions
+        .iter()
+        .copied()
+        .reduce(Vec3::min)
+        .unwrap_or_default();
+    let max = positions
+        .iter()
+        .copied()
+        .reduce(Vec3::max)
+        .unwrap_or_default();
+    let scale = (max - min) / VERTEX_STEPS;
+
+    for value in scale.to_array().iter().chain(&min.to_array()) {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    write_name(out, &key_frame.name, FRAME_NAME_BYTES)?;
+
+    // Flat axes have a zero scale and all their vertices at zero
+    let step = scale.max(Vec3::splat(f32::MIN_POSITIVE));
+    for (position, normal) in positions.iter().zip(&key_frame.normals) {
+        let v = ((position - min) / step)
+            .round()
+            .clamp(Vec3::ZERO, Vec3::splat(VERTEX_STEPS));
+        out.extend_from_slice(&[v.x as u8, v.y as u8, v.z as u8]);
+        out.push(nearest_normal(normal.xzy()));
+    }
+
+    Ok(())
+}
+
+/// Index of the precomputed normal closest to `normal`
+fn nearest_normal(normal: Vec3) -> u8 {
+    ANORMS
+        .iter()
+        .map(|n| Vec3::from_array(*n).dot(normal))
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map_or(0, |(idx, _)| idx as u8)
+}
+
+/// Cover the triangles with strips and fans
+///
+/// Greedy search as in id Software's model tools: from each triangle not
+/// yet covered every strip and fan starting on it is tried and the
+/// longest one is kept.
+fn build_gl_commands(layout: &MeshLayout) -> Vec<GlCommand> {
+    let triangles: Vec<[u32; 3]> = layout
+        .indices
+        .chunks_exact(3)
+        .map(|tri| [tri[0], tri[1], tri[2]])
+        .collect();
+    let mut used = vec![false; triangles.len()];
+    let mut commands = Vec::new();
+
+    for start in 0..triangles.len() {
+        if used[start] {
+            continue;
+        }
+
+        let mut best: Option<(GlCommandKind, Vec<u32>, Vec<usize>)> = None;
+        for kind in [GlCommandKind::Strip, GlCommandKind::Fan] {
+            for first in 0..3 {
+                let (vertices, covered) = grow_command(&triangles, &used, start, first, kind);
+                if best.as_ref().is_none_or(|(.., c)| covered.len() > c.len()) {
+                    best = Some((kind, vertices, covered));
+                }
+            }
+        }
+
+        let (kind, vertices, covered) = best.unwrap();
+        for tri in covered {
+            used[tri] = true;
+        }
+        commands.push(GlCommand {
+            kind,
+            vertices: vertices
+                .into_iter()
+                .map(|v| GlVertex {
+                    texcoord: layout.texcoords[v as usize],
+                    index: layout.vertex_map[v as usize],
+                })
+                .collect(),
+        });
+    }
+
+    commands
+}
+
+/// Strip or fan starting at vertex `first` of triangle `start`
+///
+/// Returns the mesh vertices and the triangles covered.
+fn grow_command(
+    triangles: &[[u32; 3]],
+    used: &[bool],
+    start: usize,
+    first: usize,
+    kind: GlCommandKind,
+) -> (Vec<u32>, Vec<usize>) {
+    let tri = triangles[start];
+    let mut vertices = vec![tri[first], tri[(first + 1) % 3], tri[(first + 2) % 3]];
+    let mut covered = vec![start];
+
+    loop {
+        // Edge the next triangle shares, in that triangle's winding
+        let n = vertices.len();
+        let edge = if kind == GlCommandKind::Fan {
+            (vertices[0], vertices[n - 1])
+        } else if n % 2 == 1 {
+            (vertices[n - 1], vertices[n - 2])
+        } else {
+            (vertices[n - 2], vertices[n - 1])
+        };
+
+        let next = (0..triangles.len())
+            .filter(|t| !used[*t] && !covered.contains(t))
+            .find_map(|t| {
+                let tri = triangles[t];
+                (0..3)
+                    .find(|&k| tri[k] == edge.0 && tri[(k + 1) % 3] == edge.1)
+                    .map(|k| (t, tri[(k + 2) % 3]))
+            });
+
+        let Some((t, vertex)) = next else {
+            break;
+        };
+        vertices.push(vertex);
+        covered.push(t);
+    }
+
+    (vertices, covered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::md2::{Skin, tests::tetrahedron};
+
+    /// Compare two models, allowing for vertex quantization and normal snapping
+    fn assert_same_model(expected: &Md2Asset, actual: &Md2Asset) {
+        let names = |asset: &Md2Asset| -> Vec<(String, Vec<String>)> {
+            asset
+                .animations
+                .iter()
+                .map(|anim| {
+                    let frames = anim.key_frames.iter().map(|kf| kf.name.clone()).collect();
+                    (anim.name.clone(), frames)
+                })
+                .collect()
+        };
+        assert_eq!(names(expected), names(actual));
+        assert_eq!(expected.skin_size, actual.skin_size);
+        assert_eq!(expected.skins[0].header_path, actual.skins[0].header_path);
+
+        let corners = |asset: &Md2Asset| -> Vec<(u32, Vec2)> {
+            let layout = &asset.layout;
+            layout
+                .indices
+                .iter()
+                .map(|&i| (layout.vertex_map[i as usize], layout.texcoords[i as usize]))
+                .collect()
+        };
+        assert_eq!(corners(expected), corners(actual));
+
+        let key_frames = |asset: &Md2Asset| -> Vec<KeyFrame> {
+            asset
+                .animations
+                .iter()
+                .flat_map(|anim| anim.key_frames.clone())
+                .collect()
+        };
+        for (e, a) in key_frames(expected).iter().zip(&key_frames(actual)) {
+            let min = e.positions.iter().copied().reduce(Vec3::min).unwrap();
+            let max = e.positions.iter().copied().reduce(Vec3::max).unwrap();
+            let tolerance = (max - min) / 255.0 * 0.5 + 1e-4;
+            for (pe, pa) in e.positions.iter().zip(&a.positions) {
+                assert!((pe - pa).abs().cmple(tolerance).all(), "{} vs {}", pe, pa);
+            }
+            for (ne, na) in e.normals.iter().zip(&a.normals) {
+                assert!(ne.dot(*na) > 0.95, "{} vs {}", ne, na);
+            }
+        }
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let model = tetrahedron();
+        let loaded = Md2Asset::from_bytes(&write_md2(&model).unwrap()).unwrap();
+        assert_same_model(&model, &loaded);
+
+        let reloaded = Md2Asset::from_bytes(&write_md2(&loaded).unwrap()).unwrap();
+        assert_same_model(&loaded, &reloaded);
+    }
+
+    #[test]
+    fn gl_commands_match_triangles() {
+        let mut md2 = Md2Asset::from_bytes(&write_md2(&tetrahedron()).unwrap()).unwrap();
+        assert!(!md2.gl_commands().is_empty());
+
+        let triangles = md2.layout().clone();
+        md2.use_gl_commands().unwrap();
+        let comparison = triangles.compare(md2.layout());
+        assert_eq!(comparison.triangles, [4, 4]);
+        assert_eq!(comparison.vertices, [5, 5]);
+        assert_eq!(
+            (comparison.missing, comparison.extra, comparison.flipped),
+            (0, 0, 0)
+        );
+        assert!(comparison.max_texcoord_delta < 1e-6);
+
+        // Reversing a triangle is reported as flipped
+        let mut flipped = triangles.clone();
+        flipped.indices.swap(0, 1);
+        assert_eq!(triangles.compare(&flipped).flipped, 1);
+    }
+
+    #[test]
+    fn header_skin_paths() {
+        let mut model = tetrahedron();
+        model.skins.push(Skin {
+            name: "found".to_string(),
+            path: "baseq2/models/test/found.png".into(),
+            header_path: None,
+            indexed: false,
+            fullbright: false,
+        });
+
+        // The resolved path and directory search skins aren't written
+        let loaded = Md2Asset::from_bytes(&write_md2(&model).unwrap()).unwrap();
+        assert_eq!(loaded.skins().len(), 1);
+        assert_eq!(
+            loaded.skins()[0].header_path.as_deref(),
+            Some("models/test/skin.pcx")
+        );
+    }
+
+    #[test]
+    fn name_too_long() {
+        let mut model = tetrahedron();
+        model.skins[0].header_path = Some(format!("models/{}/skin.pcx", "x".repeat(60)));
+        assert!(matches!(
+            write_md2(&model),
+            Err(Md2WriterError::NameTooLong { max: 63, .. })
+        ));
+
+        let mut model = tetrahedron();
+        model.animations[0].key_frames[0].name = "a_very_long_frame".to_string();
+        assert!(matches!(
+            write_md2(&model),
+            Err(Md2WriterError::NameTooLong { max: 15, .. })
+        ));
+    }
+}
@@ -0,0 +1,569 @@
+//! Quake 2 PAK archive reading and asset source
+//!
+//! Assets inside archives are addressed by game directory, e.g.
+//! `pak://baseq2/models/monsters/tank/tris.md2` is looked up in
+//! `baseq2/pak*.pak` under the source root.
+use bevy::asset::io::{
+    AssetReader, AssetReaderError, AssetSourceBuilder, PathStream, Reader, VecReader,
+};
+use bevy::prelude::*;
+use bevy::tasks::futures_lite::stream;
+use blocking::unblock;
+use glob::glob;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+/// Name of the asset source registered by [`PakAssetPlugin`]
+pub const PAK_SOURCE: &str = "pak";
+
+#[derive(Debug, Error)]
+pub enum PakError {
+    #[error("Failed to read PAK file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid PAK format: {0}")]
+    InvalidFormat(String),
+    #[error("File not found in PAK: {0}")]
+    NotFound(String),
+}
+
+const HDR_BYTES: usize = 12;
+const ENTRY_BYTES: usize = 64;
+const NAME_BYTES: usize = 56;
+
+/// PAK file header
+#[derive(Debug)]
+struct Header {
+    dir_offset: u64,
+    dir_len: usize,
+}
+
+impl Header {
+    fn from_bytes(data: &[u8]) -> Result<Header, PakError> {
+        if data.len() < HDR_BYTES {
+            return Err(PakError::InvalidFormat(
+                "Not enough bytes for header".to_string(),
+            ));
+        }
+
+        if &data[0..4] != b"PACK" {
+            return Err(PakError::InvalidFormat("Not a PAK file".to_string()));
+        }
+
+        let dir_offset = i32::from_le_bytes(data[4..8].try_into().unwrap());
+        let dir_len = i32::from_le_bytes(data[8..12].try_into().unwrap());
+
+        let dir_offset = u64::try_from(dir_offset).map_err(|err| {
+            PakError::InvalidFormat(format!("Invalid directory offset - {}", err))
+        })?;
+        let dir_len = usize::try_from(dir_len).map_err(|err| {
+            PakError::InvalidFormat(format!("Invalid directory length - {}", err))
+        })?;
+
+        if dir_len % ENTRY_BYTES != 0 {
+            return Err(PakError::InvalidFormat(
+                "Directory length is not a multiple of the entry size".to_string(),
+            ));
+        }
+
+        Ok(Header {
+            dir_offset,
+            dir_len,
+        })
+    }
+
+    fn check_len(&self, file_len: u64) -> Result<(), PakError> {
+        if self.dir_offset + self.dir_len as u64 > file_len {
+            return Err(PakError::InvalidFormat(
+                "Directory extends past end of file".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// PAK directory entry
+#[derive(Debug, Clone)]
+struct Entry {
+    offset: u64,
+    len: usize,
+}
+
+impl Entry {
+    fn from_bytes(data: &[u8]) -> Result<(String, Entry), PakError> {
+        if data.len() < ENTRY_BYTES {
+            return Err(PakError::InvalidFormat(
+                "Not enough bytes for directory entry".to_string(),
+            ));
+        }
+
+        let name = &data[0..NAME_BYTES];
+        let end = name.iter().position(|&c| c == 0).unwrap_or(NAME_BYTES);
+        let name = String::from_utf8_lossy(&name[0..end]).to_string();
+
+        let offset = i32::from_le_bytes(data[56..60].try_into().unwrap());
+        let len = i32::from_le_bytes(data[60..64].try_into().unwrap());
+
+        let offset = u64::try_from(offset).map_err(|err| {
+            PakError::InvalidFormat(format!("Invalid offset for {} - {}", name, err))
+        })?;
+        let len = usize::try_from(len).map_err(|err| {
+            PakError::InvalidFormat(format!("Invalid length for {} - {}", name, err))
+        })?;
+
+        Ok((name, Entry { offset, len }))
+    }
+}
+
+/// Where the file contents of a [`PakFile`] are read from
+#[derive(Debug)]
+enum Contents {
+    File(PathBuf),
+    Memory(Vec<u8>),
+}
+
+/// An opened PAK archive
+///
+/// Only the directory is kept in memory, file contents
+/// are read from disk on demand.
+#[derive(Debug)]
+pub struct PakFile {
+    contents: Contents,
+    entries: HashMap<String, Entry>,
+}
+
+impl PakFile {
+    pub fn open(path: &Path) -> Result<PakFile, PakError> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+
+        let mut hdr_bytes = [0u8; HDR_BYTES];
+        file.read_exact(&mut hdr_bytes)?;
+        let header = Header::from_bytes(&hdr_bytes)?;
+        header.check_len(file_len)?;
+
+        let mut dir_bytes = vec![0u8; header.dir_len];
+        file.seek(SeekFrom::Start(header.dir_offset))?;
+        file.read_exact(&mut dir_bytes)?;
+
+        Ok(PakFile {
+            contents: Contents::File(path.to_path_buf()),
+            entries: parse_directory(&dir_bytes, file_len)?,
+        })
+    }
+
+    /// Parse an archive held in memory
+    pub fn from_bytes(data: Vec<u8>) -> Result<PakFile, PakError> {
+        let header = Header::from_bytes(&data)?;
+        let file_len = data.len() as u64;
+        header.check_len(file_len)?;
+
+        let dir_start = header.dir_offset as usize;
+        let dir_bytes = &data[dir_start..dir_start + header.dir_len];
+
+        Ok(PakFile {
+            entries: parse_directory(dir_bytes, file_len)?,
+            contents: Contents::Memory(data),
+        })
+    }
+
+    /// Path of the archive on disk, `None` if it was read from memory
+    pub fn path(&self) -> Option<&Path> {
+        match &self.contents {
+            Contents::File(path) => Some(path),
+            Contents::Memory(_) => None,
+        }
+    }
+
+    /// Names of all files in the archive
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(name)
+    }
+
+    /// Read the contents of a file in the archive
+    ///
+    /// This blocks on file I/O for archives on disk.
+    pub fn read(&self, name: &str) -> Result<Vec<u8>, PakError> {
+        let entry = self
+            .entries
+            .get(name)
+            .ok_or_else(|| PakError::NotFound(name.to_string()))?;
+
+        match &self.contents {
+            Contents::File(path) => {
+                let mut file = File::open(path)?;
+                let mut data = vec![0u8; entry.len];
+                file.seek(SeekFrom::Start(entry.offset))?;
+                file.read_exact(&mut data)?;
+                Ok(data)
+            }
+            Contents::Memory(data) => {
+                let start = entry.offset as usize;
+                Ok(data[start..start + entry.len].to_vec())
+            }
+        }
+    }
+}
+
+/// Parse the directory entries of an archive of `file_len` bytes
+fn parse_directory(dir_bytes: &[u8], file_len: u64) -> Result<HashMap<String, Entry>, PakError> {
+    let mut entries = HashMap::with_capacity(dir_bytes.len() / ENTRY_BYTES);
+
+    for chunk in dir_bytes.chunks_exact(ENTRY_BYTES) {
+        let (name, entry) = Entry::from_bytes(chunk)?;
+        if entry.offset + entry.len as u64 > file_len {
+            return Err(PakError::InvalidFormat(format!(
+                "{} extends past end of file",
+                name
+            )));
+        }
+        entries.insert(name, entry);
+    }
+
+    Ok(entries)
+}
+
+/// Open all `pak*.pak` archives in a game directory
+///
+/// Archives are returned in load order (pak0, pak1, ..., pak10),
+/// files in later archives override those in earlier ones.
+pub fn open_game_paks(game_dir: &Path) -> Result<Vec<PakFile>, PakError> {
+    let glob_path = game_dir.join("pak*.pak");
+    let pattern = glob_path.to_str().unwrap();
+    let mut paths: Vec<PathBuf> = glob(pattern).unwrap().filter_map(Result::ok).collect();
+
+    // Sort numerically so pak10 comes after pak9
+    paths.sort_by_key(|p| (p.as_os_str().len(), p.clone()));
+
+    paths.iter().map(|p| PakFile::open(p)).collect()
+}
+
+/// Find all game directories under `root` containing PAK archives
+pub fn find_game_dirs(root: &Path) -> Vec<String> {
+    let glob_path = root.join("*").join("pak*.pak");
+    let pattern = glob_path.to_str().unwrap();
+    let mut games = BTreeSet::new();
+
+    for entry in glob(pattern).unwrap().filter_map(Result::ok) {
+        if let Some(game) = entry
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(|name| name.to_str())
+        {
+            games.insert(game.to_string());
+        }
+    }
+
+    games.into_iter().collect()
+}
+
+/// [`AssetReader`] for files stored in PAK archives
+///
+/// The first path component selects the game directory under `root`,
+/// the remainder is the file name inside the archives.
+pub struct PakAssetReader {
+    root: PathBuf,
+    games: RwLock<HashMap<String, Arc<Vec<PakFile>>>>,
+}
+
+impl PakAssetReader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            games: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Use already opened archives for a game directory instead of the ones on disk
+    pub fn insert_game(&self, game: &str, paks: Vec<PakFile>) {
+        self.games
+            .write()
+            .unwrap()
+            .insert(game.to_string(), Arc::new(paks));
+    }
+
+    async fn game_paks(&self, game: &str) -> Result<Arc<Vec<PakFile>>, AssetReaderError> {
+        if let Some(paks) = self.games.read().unwrap().get(game) {
+            return Ok(paks.clone());
+        }
+
+        let game_dir = self.root.join(game);
+        let paks = unblock(move || open_game_paks(&game_dir))
+            .await
+            .map_err(to_reader_error)?;
+        let paks = Arc::new(paks);
+        self.games
+            .write()
+            .unwrap()
+            .insert(game.to_string(), paks.clone());
+        Ok(paks)
+    }
+
+    /// Split an asset path into the game directory and archive file name
+    fn split_path(path: &Path) -> Option<(String, String)> {
+        let mut components = path.iter().map(|c| c.to_string_lossy());
+        let game = components.next()?.to_string();
+        let name = components.collect::<Vec<_>>().join("/");
+        Some((game, name))
+    }
+
+    async fn read_bytes(&self, path: &Path) -> Result<Vec<u8>, AssetReaderError> {
+        let not_found = || AssetReaderError::NotFound(path.to_path_buf());
+        let (game, name) = PakAssetReader::split_path(path).ok_or_else(not_found)?;
+        let paks = self.game_paks(&game).await?;
+        let idx = paks
+            .iter()
+            .rposition(|pak| pak.contains(&name))
+            .ok_or_else(not_found)?;
+
+        let data = if paks[idx].path().is_some() {
+            unblock(move || paks[idx].read(&name)).await
+        } else {
+            paks[idx].read(&name)
+        };
+        data.map_err(to_reader_error)
+    }
+}
+
+fn to_reader_error(err: PakError) -> AssetReaderError {
+    match err {
+        PakError::Io(err) => AssetReaderError::Io(Arc::new(err)),
+        PakError::NotFound(name) => AssetReaderError::NotFound(PathBuf::from(name)),
+        err => AssetReaderError::Io(Arc::new(std::io::Error::other(err))),
+    }
+}
+
+impl AssetReader for PakAssetReader {
+    async fn read<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        self.read_bytes(path).await.map(VecReader::new)
+    }
+
+    async fn read_meta<'a>(&'a self, path: &'a Path) -> Result<impl Reader + 'a, AssetReaderError> {
+        // PAK archives never contain .meta files
+        Err::<VecReader, _>(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> Result<Box<PathStream>, AssetReaderError> {
+        let (game, dir) = PakAssetReader::split_path(path)
+            .ok_or_else(|| AssetReaderError::NotFound(path.to_path_buf()))?;
+        let paks = self.game_paks(&game).await?;
+        let prefix = if dir.is_empty() {
+            dir
+        } else {
+            format!("{}/", dir)
+        };
+
+        let mut children = BTreeSet::new();
+        for name in paks.iter().flat_map(|pak| pak.names()) {
+            if let Some(rest) = name.strip_prefix(&prefix) {
+                let child = rest.split('/').next().unwrap();
+                children.insert(path.join(child));
+            }
+        }
+
+        let stream: Box<PathStream> = Box::new(stream::iter(children));
+        Ok(stream)
+    }
+
+    async fn is_directory<'a>(&'a self, path: &'a Path) -> Result<bool, AssetReaderError> {
+        let Some((game, dir)) = PakAssetReader::split_path(path) else {
+            return Ok(true);
+        };
+        let paks = self.game_paks(&game).await?;
+        if dir.is_empty() {
+            return Ok(!paks.is_empty());
+        }
+
+        let prefix = format!("{}/", dir);
+        Ok(paks
+            .iter()
+            .flat_map(|pak| pak.names())
+            .any(|name| name.starts_with(&prefix)))
+    }
+}
+
+/// Plugin to register the `pak://` asset source
+///
+/// This must be added before `DefaultPlugins`.
+pub struct PakAssetPlugin {
+    /// Directory containing the game directories
+    pub root: PathBuf,
+}
+
+impl Default for PakAssetPlugin {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from("assets"),
+        }
+    }
+}
+
+impl Plugin for PakAssetPlugin {
+    fn build(&self, app: &mut App) {
+        let root = self.root.clone();
+        app.register_asset_source(
+            PAK_SOURCE,
+            AssetSourceBuilder::default()
+                .with_reader(move || Box::new(PakAssetReader::new(root.clone()))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::tasks::{block_on, futures_lite::StreamExt};
+
+    /// Build a PAK archive with the directory after the file contents
+    fn pak_bytes(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = vec![0u8; HDR_BYTES];
+        let mut dir = Vec::new();
+
+        for (name, contents) in files {
+            let mut entry = [0u8; ENTRY_BYTES];
+            entry[..name.len()].copy_from_slice(name.as_bytes());
+            entry[56..60].copy_from_slice(&(data.len() as i32).to_le_bytes());
+            entry[60..64].copy_from_slice(&(contents.len() as i32).to_le_bytes());
+            dir.extend_from_slice(&entry);
+            data.extend_from_slice(contents);
+        }
+
+        let dir_offset = data.len() as i32;
+        data[0..4].copy_from_slice(b"PACK");
+        data[4..8].copy_from_slice(&dir_offset.to_le_bytes());
+        data[8..12].copy_from_slice(&(dir.len() as i32).to_le_bytes());
+        data.extend_from_slice(&dir);
+        data
+    }
+
+    fn read_to_end(reader: &PakAssetReader, path: &str) -> Result<Vec<u8>, AssetReaderError> {
+        block_on(async {
+            let mut reader = reader.read(Path::new(path)).await?;
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).await?;
+            Ok(data)
+        })
+    }
+
+    #[test]
+    fn header_and_directory() {
+        let data = pak_bytes(&[
+            ("models/a/tris.md2", b"model"),
+            ("models/a/skin.pcx", b"skin"),
+        ]);
+
+        let header = Header::from_bytes(&data).unwrap();
+        assert_eq!(header.dir_offset, (HDR_BYTES + 9) as u64);
+        assert_eq!(header.dir_len, 2 * ENTRY_BYTES);
+
+        let pak = PakFile::from_bytes(data).unwrap();
+        let mut names: Vec<_> = pak.names().collect();
+        names.sort();
+        assert_eq!(names, ["models/a/skin.pcx", "models/a/tris.md2"]);
+        assert!(pak.path().is_none());
+        assert_eq!(pak.read("models/a/tris.md2").unwrap(), b"model");
+        assert_eq!(pak.read("models/a/skin.pcx").unwrap(), b"skin");
+        assert!(matches!(pak.read("missing"), Err(PakError::NotFound(_))));
+    }
+
+    #[test]
+    fn bad_magic() {
+        let mut data = pak_bytes(&[("a", b"a")]);
+        data[0..4].copy_from_slice(b"IDP2");
+        assert!(matches!(
+            PakFile::from_bytes(data),
+            Err(PakError::InvalidFormat(_))
+        ));
+
+        assert!(matches!(
+            Header::from_bytes(b"PACK"),
+            Err(PakError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn out_of_bounds() {
+        // Entry contents past the end of the archive
+        let mut data = pak_bytes(&[("a", b"a")]);
+        let entry = HDR_BYTES + 1;
+        data[entry + 60..entry + 64].copy_from_slice(&100i32.to_le_bytes());
+        assert!(matches!(
+            PakFile::from_bytes(data),
+            Err(PakError::InvalidFormat(_))
+        ));
+
+        // Directory past the end of the archive
+        let mut data = pak_bytes(&[("a", b"a")]);
+        data[8..12].copy_from_slice(&(2 * ENTRY_BYTES as i32).to_le_bytes());
+        assert!(matches!(
+            PakFile::from_bytes(data),
+            Err(PakError::InvalidFormat(_))
+        ));
+
+        // Negative entry offset
+        let mut data = pak_bytes(&[("a", b"a")]);
+        data[entry + 56..entry + 60].copy_from_slice(&(-1i32).to_le_bytes());
+        assert!(matches!(
+            PakFile::from_bytes(data),
+            Err(PakError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn asset_reader() {
+        let pak0 = pak_bytes(&[
+            ("models/a/tris.md2", b"pak0 model"),
+            ("models/a/skin.pcx", b"skin"),
+        ]);
+        let pak1 = pak_bytes(&[("models/a/tris.md2", b"pak1 model")]);
+
+        let reader = PakAssetReader::new("missing root");
+        reader.insert_game(
+            "baseq2",
+            vec![
+                PakFile::from_bytes(pak0).unwrap(),
+                PakFile::from_bytes(pak1).unwrap(),
+            ],
+        );
+
+        // Later archives override earlier ones
+        assert_eq!(
+            read_to_end(&reader, "baseq2/models/a/tris.md2").unwrap(),
+            b"pak1 model"
+        );
+        assert_eq!(
+            read_to_end(&reader, "baseq2/models/a/skin.pcx").unwrap(),
+            b"skin"
+        );
+        assert!(matches!(
+            read_to_end(&reader, "baseq2/models/a/missing.pcx"),
+            Err(AssetReaderError::NotFound(_))
+        ));
+
+        let children: Vec<_> = block_on(async {
+            let dir = reader.read_directory(Path::new("baseq2/models/a")).await?;
+            Ok::<_, AssetReaderError>(dir.collect().await)
+        })
+        .unwrap();
+        assert_eq!(
+            children,
+            [
+                PathBuf::from("baseq2/models/a/skin.pcx"),
+                PathBuf::from("baseq2/models/a/tris.md2")
+            ]
+        );
+
+        assert!(block_on(reader.is_directory(Path::new("baseq2/models"))).unwrap());
+        assert!(!block_on(reader.is_directory(Path::new("baseq2/maps"))).unwrap());
+    }
+}
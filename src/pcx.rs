@@ -1,6 +1,10 @@
 //! PiCture eXchange image format asset loading
-use bevy::asset::{AssetLoader, LoadContext, RenderAssetUsages};
+use bevy::asset::{
+    AssetLoader, LoadContext, ParseAssetPathError, ReadAssetBytesError, RenderAssetUsages,
+};
 use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 /// Custom error type for PCX loading
@@ -10,28 +14,125 @@ pub enum PcxLoaderError {
     Io(#[from] std::io::Error),
     #[error("Invalid PCX format: {0}")]
     InvalidFormat(String),
+    #[error("Invalid palette path: {0}")]
+    PalettePath(#[from] ParseAssetPathError),
+    #[error("Failed to read palette: {0}")]
+    ReadPalette(#[from] ReadAssetBytesError),
 }
 
 /// The PCX asset loader
 #[derive(Default)]
 pub struct PcxLoader;
 
-const HDR_BYTES: usize = 128;
+/// Settings for the [`PcxLoader`]
+///
+/// Fields left out of a `.meta` file keep their defaults.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PcxLoaderSettings {
+    /// Keep the palette indices of 8-bit images
+    ///
+    /// The image is loaded as an `R8Unorm` texture of palette indices and
+    /// the palette as a 256x1 image labeled [`PALETTE_LABEL`], so colors can
+    /// be looked up or remapped in a shader.
+    pub indexed: bool,
+    /// Add an emissive mask of the fullbright pixels, labeled [`FULLBRIGHT_LABEL`]
+    ///
+    /// Pixels using the fullbright palette entries keep their color and all
+    /// others are black. Images without a palette have no fullbright pixels.
+    pub fullbright: bool,
+    /// Color space of the color images
+    pub color_space: PcxColorSpace,
+    /// PCX whose palette is used by 8-bit images without one
+    ///
+    /// Resolved relative to the image, a leading `/` starts at the root of
+    /// its asset source, e.g. `/pics/colormap.pcx` for Quake 2 HUD pics.
+    pub palette: Option<String>,
+    /// Palette index of fully transparent pixels, e.g. 255 in Quake 2
+    pub transparent_index: Option<u8>,
+}
+
+/// Color space of the color images loaded by the [`PcxLoader`]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PcxColorSpace {
+    /// Colors are sRGB encoded, as for textures viewed as images
+    #[default]
+    Srgb,
+    /// Colors are used as is, as for data textures
+    Linear,
+}
+
+impl PcxColorSpace {
+    fn texture_format(self) -> TextureFormat {
+        match self {
+            PcxColorSpace::Srgb => TextureFormat::Rgba8UnormSrgb,
+            PcxColorSpace::Linear => TextureFormat::Rgba8Unorm,
+        }
+    }
+}
+
+/// Label of the palette image loaded with [`PcxLoaderSettings::indexed`]
+pub const PALETTE_LABEL: &str = "palette";
+
+/// Label of the emissive mask loaded with [`PcxLoaderSettings::fullbright`]
+pub const FULLBRIGHT_LABEL: &str = "fullbright";
+
+/// First fullbright entry of the Quake 2 palette, the rest up to 255 are too
+pub const FIRST_FULLBRIGHT: u8 = 208;
+
+pub(crate) const HDR_BYTES: usize = 128;
+
+/// Number of palette entries in 8-bit images
+const PALETTE_COLORS: usize = 256;
+
+/// Size of the palette trailing 8-bit images, after its marker byte
+const PALETTE_BYTES: usize = PALETTE_COLORS * 3;
+
+/// Start of the 16 color palette of EGA and CGA images in the header
+const HEADER_PALETTE_OFFSET: usize = 16;
+
+/// Colors of monochrome images
+const MONO_PALETTE: [u8; 6] = [0, 0, 0, 255, 255, 255];
 
 impl AssetLoader for PcxLoader {
     type Asset = Image;
-    type Settings = ();
+    type Settings = PcxLoaderSettings;
     type Error = PcxLoaderError;
 
     async fn load(
         &self,
         reader: &mut dyn bevy::asset::io::Reader,
-        _settings: &Self::Settings,
-        _load_context: &mut LoadContext<'_>,
+        settings: &Self::Settings,
+        load_context: &mut LoadContext<'_>,
     ) -> Result<Self::Asset, Self::Error> {
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes).await?;
-        let image_data = parse_pcx(&bytes)?;
+
+        let external_palette = match &settings.palette {
+            Some(path) if needs_external_palette(&bytes) => {
+                let path = load_context.asset_path().resolve_embed(path)?;
+                let palette_bytes = load_context.read_asset_bytes(&path).await?;
+                let palette = trailing_palette(&palette_bytes).ok_or_else(|| {
+                    PcxLoaderError::InvalidFormat(format!("{} has no palette", path))
+                })?;
+                Some(palette.to_vec())
+            }
+            _ => None,
+        };
+        let external_palette = external_palette.as_deref();
+
+        if settings.fullbright {
+            let fullbright = parse_fullbright(&bytes, settings, external_palette)?;
+            load_context.add_labeled_asset(FULLBRIGHT_LABEL.to_string(), fullbright);
+        }
+
+        if settings.indexed {
+            let (indices, palette) = parse_pcx_indexed(&bytes, settings, external_palette)?;
+            load_context.add_labeled_asset(PALETTE_LABEL.to_string(), palette);
+            return Ok(indices);
+        }
+
+        let image_data = parse_pcx(&bytes, settings, external_palette)?;
         Ok(image_data)
     }
 
@@ -40,8 +141,8 @@ impl AssetLoader for PcxLoader {
     }
 }
 
-/// Parse PCX data and convert to Bevy Image
-fn parse_pcx(data: &[u8]) -> Result<Image, PcxLoaderError> {
+/// Check the header and extract the image dimensions
+fn parse_header(data: &[u8]) -> Result<(u32, u32), PcxLoaderError> {
     if data.len() < HDR_BYTES {
         return Err(PcxLoaderError::InvalidFormat(
             "File too small to be valid PCX".to_string(),
@@ -61,22 +162,137 @@ fn parse_pcx(data: &[u8]) -> Result<Image, PcxLoaderError> {
     let xmax = u16::from_le_bytes([data[8], data[9]]) as u32;
     let ymax = u16::from_le_bytes([data[10], data[11]]) as u32;
 
-    let width = xmax - xmin + 1;
-    let height = ymax - ymin + 1;
-    let mut rgba_data = vec![0u8; (width * height * 4) as usize];
+    Ok((xmax - xmin + 1, ymax - ymin + 1))
+}
 
-    decode_pcx_data(data, &mut rgba_data, width, height)?;
+/// Whether PCX data is an 8-bit image, whose pixels are palette indices
+///
+/// Only the header is looked at.
+pub(crate) fn has_palette_indices(data: &[u8]) -> bool {
+    data.len() >= HDR_BYTES && (data[3], data[65]) == (8, 1)
+}
 
-    Ok(Image::new(
-        bevy::render::render_resource::Extent3d {
+fn new_image(width: u32, height: u32, data: Vec<u8>, format: TextureFormat) -> Image {
+    Image::new(
+        Extent3d {
             width,
             height,
             depth_or_array_layers: 1,
         },
-        bevy::render::render_resource::TextureDimension::D2,
-        rgba_data,
-        bevy::render::render_resource::TextureFormat::Rgba8UnormSrgb,
+        TextureDimension::D2,
+        data,
+        format,
         RenderAssetUsages::default(),
+    )
+}
+
+/// The 256 color palette at the end of 8-bit PCX data, if it has one
+fn trailing_palette(data: &[u8]) -> Option<&[u8]> {
+    let marker = data.len().checked_sub(PALETTE_BYTES + 1)?;
+    (marker >= HDR_BYTES && data[marker] == 0x0C).then(|| &data[marker + 1..])
+}
+
+/// Whether PCX data is an 8-bit image without a palette of its own
+///
+/// Other bit depths never use [`PcxLoaderSettings::palette`], so it is only
+/// read, and depended on, for these.
+fn needs_external_palette(data: &[u8]) -> bool {
+    has_palette_indices(data) && trailing_palette(data).is_none()
+}
+
+/// Parse PCX data and convert to Bevy Image
+///
+/// `external_palette` is used by 8-bit images without a palette.
+pub(crate) fn parse_pcx(
+    data: &[u8],
+    settings: &PcxLoaderSettings,
+    external_palette: Option<&[u8]>,
+) -> Result<Image, PcxLoaderError> {
+    let (width, height) = parse_header(data)?;
+    let mut rgba_data = vec![0u8; (width * height * 4) as usize];
+
+    decode_pcx_data(
+        data,
+        &mut rgba_data,
+        width,
+        height,
+        settings,
+        external_palette,
+    )?;
+
+    Ok(new_image(
+        width,
+        height,
+        rgba_data,
+        settings.color_space.texture_format(),
+    ))
+}
+
+/// Parse 8-bit PCX data into a palette index image and a palette image
+pub(crate) fn parse_pcx_indexed(
+    data: &[u8],
+    settings: &PcxLoaderSettings,
+    external_palette: Option<&[u8]>,
+) -> Result<(Image, Image), PcxLoaderError> {
+    let (width, height) = parse_header(data)?;
+    let bits_per_pixel = data[3];
+    let planes = data[65];
+    if (bits_per_pixel, planes) != (8, 1) {
+        return Err(PcxLoaderError::InvalidFormat(format!(
+            "Only 8-bit PCX has palette indices, not {} bpp, {} planes",
+            bits_per_pixel, planes
+        )));
+    }
+
+    let bytes_per_line = u16::from_le_bytes([data[66], data[67]]) as usize;
+    let (indices, palette) =
+        decode_8bit_indices(data, width, height, bytes_per_line, external_palette)?;
+
+    let mut palette_rgba: Vec<u8> = palette
+        .chunks_exact(3)
+        .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+        .collect();
+    if let Some(index) = settings.transparent_index {
+        palette_rgba[index as usize * 4 + 3] = 0;
+    }
+
+    Ok((
+        new_image(width, height, indices, TextureFormat::R8Unorm),
+        new_image(
+            PALETTE_COLORS as u32,
+            1,
+            palette_rgba,
+            settings.color_space.texture_format(),
+        ),
+    ))
+}
+
+/// Parse the emissive mask of the fullbright pixels of PCX data
+pub(crate) fn parse_fullbright(
+    data: &[u8],
+    settings: &PcxLoaderSettings,
+    external_palette: Option<&[u8]>,
+) -> Result<Image, PcxLoaderError> {
+    let (width, height) = parse_header(data)?;
+    let mut rgba_data: Vec<u8> = [0, 0, 0, 255].repeat((width * height) as usize);
+
+    if (data[3], data[65]) == (8, 1) {
+        let bytes_per_line = u16::from_le_bytes([data[66], data[67]]) as usize;
+        let (indices, palette) =
+            decode_8bit_indices(data, width, height, bytes_per_line, external_palette)?;
+        for (dst, &index) in rgba_data.chunks_exact_mut(4).zip(&indices) {
+            if index >= FIRST_FULLBRIGHT && Some(index) != settings.transparent_index {
+                let palette_idx = index as usize * 3;
+                dst[..3].copy_from_slice(&palette[palette_idx..palette_idx + 3]);
+            }
+        }
+    }
+
+    Ok(new_image(
+        width,
+        height,
+        rgba_data,
+        settings.color_space.texture_format(),
     ))
 }
 
@@ -86,6 +302,8 @@ fn decode_pcx_data(
     output: &mut [u8],
     width: u32,
     height: u32,
+    settings: &PcxLoaderSettings,
+    external_palette: Option<&[u8]>,
 ) -> Result<(), PcxLoaderError> {
     if data.len() < HDR_BYTES {
         return Err(PcxLoaderError::InvalidFormat(
@@ -93,28 +311,43 @@ fn decode_pcx_data(
         ));
     }
 
-    let encoding = data[2];
     let bits_per_pixel = data[3];
     let planes = data[65] as u32;
     let bytes_per_line = u16::from_le_bytes([data[66], data[67]]) as usize;
 
-    // Verify RLE encoding
-    if encoding != 1 {
-        return Err(PcxLoaderError::InvalidFormat(
-            "Only RLE encoding is supported".to_string(),
-        ));
-    }
-
     // Decode based on bit depth and planes
     match (bits_per_pixel, planes) {
         (8, 1) => {
             // 8-bit indexed color
-            decode_8bit_indexed(data, output, width, height, bytes_per_line)
+            let (indices, palette) =
+                decode_8bit_indices(data, width, height, bytes_per_line, external_palette)?;
+            decode_indexed(&indices, palette, output, settings.transparent_index);
+            Ok(())
         }
         (8, 3) | (8, 4) => {
-            // 24-bit or 32-bit RGB
+            // 24-bit RGB or 32-bit RGBA
             decode_24bit_rgb(data, output, width, height, bytes_per_line, planes)
         }
+        (1, 1) => {
+            // Monochrome, black and white
+            let indices = decode_packed_indices(data, width, height, bytes_per_line, 1, 1)?;
+            decode_indexed(&indices, &MONO_PALETTE, output, settings.transparent_index);
+            Ok(())
+        }
+        (1, 2..=4) | (2 | 4, 1) => {
+            // CGA or EGA with the 16 color palette in the header
+            let indices = decode_packed_indices(
+                data,
+                width,
+                height,
+                bytes_per_line,
+                bits_per_pixel as usize,
+                planes as usize,
+            )?;
+            let palette = &data[HEADER_PALETTE_OFFSET..HEADER_PALETTE_OFFSET + 48];
+            decode_indexed(&indices, palette, output, settings.transparent_index);
+            Ok(())
+        }
         _ => Err(PcxLoaderError::InvalidFormat(format!(
             "Unsupported PCX format: {} bpp, {} planes",
             bits_per_pixel, planes
@@ -122,60 +355,66 @@ fn decode_pcx_data(
     }
 }
 
-/// Decode 8-bit indexed PCX with 256-color palette
-fn decode_8bit_indexed(
-    data: &[u8],
+/// Convert palette indices to RGBA
+fn decode_indexed(
+    indices: &[u8],
+    palette: &[u8],
     output: &mut [u8],
+    transparent_index: Option<u8>,
+) {
+    for (dst, &index) in output.chunks_exact_mut(4).zip(indices) {
+        let palette_idx = index as usize * 3;
+        dst[0] = palette[palette_idx]; // R
+        dst[1] = palette[palette_idx + 1]; // G
+        dst[2] = palette[palette_idx + 2]; // B
+        dst[3] = if Some(index) == transparent_index {
+            0
+        } else {
+            255
+        }; // A
+    }
+}
+
+/// Decode the palette indices of an 8-bit PCX, along with its palette
+///
+/// Files without a trailing palette use `external_palette`.
+fn decode_8bit_indices<'a>(
+    data: &'a [u8],
     width: u32,
     height: u32,
     bytes_per_line: usize,
-) -> Result<(), PcxLoaderError> {
-    // Extract palette from end of file (last 768 bytes after marker 0x0C)
-    let palette_offset = data
-        .len()
-        .checked_sub(769)
-        .ok_or_else(|| PcxLoaderError::InvalidFormat("File too small for palette".to_string()))?;
-
-    if data[palette_offset] != 0x0C {
-        return Err(PcxLoaderError::InvalidFormat(
-            "Invalid palette marker".to_string(),
-        ));
-    }
+    external_palette: Option<&'a [u8]>,
+) -> Result<(Vec<u8>, &'a [u8]), PcxLoaderError> {
+    // The palette is the last 768 bytes, after a 0x0C marker
+    let (compressed, palette) = match (trailing_palette(data), external_palette) {
+        (Some(palette), _) => (&data[HDR_BYTES..data.len() - PALETTE_BYTES - 1], palette),
+        (None, Some(palette)) => (&data[HDR_BYTES..], palette),
+        (None, None) => {
+            return Err(PcxLoaderError::InvalidFormat(
+                "No palette in file or settings".to_string(),
+            ));
+        }
+    };
 
-    let palette = &data[palette_offset + 1..];
-    let compressed = &data[HDR_BYTES..palette_offset];
     let total_bytes = bytes_per_line * height as usize;
-    let decompressed = decompress_rle_data(compressed, total_bytes)?;
+    let decompressed = decode_scanlines(data[2], compressed, total_bytes)?;
 
-    // Convert indexed data to RGBA
+    // Drop the padding at the end of each scanline
+    let mut indices = Vec::with_capacity((width * height) as usize);
     for y in 0..height as usize {
-        for x in 0..width as usize {
-            let src_idx = y * bytes_per_line + x;
-            if src_idx >= decompressed.len() {
-                return Err(PcxLoaderError::InvalidFormat(
-                    "Insufficient data".to_string(),
-                ));
-            }
-
-            let palette_idx = decompressed[src_idx] as usize * 3;
-            if palette_idx + 2 >= palette.len() {
-                return Err(PcxLoaderError::InvalidFormat(
-                    "Invalid palette index".to_string(),
-                ));
-            }
-
-            let dst_idx = (y * width as usize + x) * 4;
-            output[dst_idx] = palette[palette_idx]; // R
-            output[dst_idx + 1] = palette[palette_idx + 1]; // G
-            output[dst_idx + 2] = palette[palette_idx + 2]; // B
-            output[dst_idx + 3] = 255; // A
-        }
+        let start = y * bytes_per_line;
+        let Some(line) = decompressed.get(start..start + width as usize) else {
+            return Err(PcxLoaderError::InvalidFormat(
+                "Insufficient data".to_string(),
+            ));
+        };
+        indices.extend_from_slice(line);
     }
 
-    Ok(())
+    Ok((indices, palette))
 }
 
-/// Decode 24-bit RGB PCX
+/// Decode 24-bit RGB or 32-bit RGBA PCX
 fn decode_24bit_rgb(
     data: &[u8],
     output: &mut [u8],
@@ -185,21 +424,23 @@ fn decode_24bit_rgb(
     planes: u32,
 ) -> Result<(), PcxLoaderError> {
     let total_bytes = bytes_per_line * planes as usize * height as usize;
-    let decompressed = decompress_rle_data(&data[HDR_BYTES..], total_bytes)?;
+    let decompressed = decode_scanlines(data[2], &data[HDR_BYTES..], total_bytes)?;
 
-    // Convert planar RGB to interleaved RGBA
+    // Convert planar RGB(A) to interleaved RGBA
     for y in 0..height as usize {
         let scanline_offset = y * bytes_per_line * planes as usize;
 
         for x in 0..width as usize {
             let dst_idx = (y * width as usize + x) * 4;
 
-            // RGB planes are stored sequentially in each scanline
+            // RGB(A) planes are stored sequentially in each scanline
             let r_offset = scanline_offset + x;
             let g_offset = scanline_offset + bytes_per_line + x;
             let b_offset = scanline_offset + bytes_per_line * 2 + x;
+            let a_offset = scanline_offset + bytes_per_line * 3 + x;
+            let last_offset = if planes == 4 { a_offset } else { b_offset };
 
-            if b_offset >= decompressed.len() {
+            if last_offset >= decompressed.len() {
                 return Err(PcxLoaderError::InvalidFormat(
                     "Insufficient RGB data".to_string(),
                 ));
@@ -208,13 +449,82 @@ fn decode_24bit_rgb(
             output[dst_idx] = decompressed[r_offset]; // R
             output[dst_idx + 1] = decompressed[g_offset]; // G
             output[dst_idx + 2] = decompressed[b_offset]; // B
-            output[dst_idx + 3] = 255; // A
+            output[dst_idx + 3] = if planes == 4 {
+                decompressed[a_offset]
+            } else {
+                255
+            }; // A
         }
     }
 
     Ok(())
 }
 
+/// Palette indices of images with less than 8 bits per pixel
+///
+/// Pixels are packed from the most significant bit of each scanline, with
+/// the bits of each plane stored one after another and forming the index
+/// from the least significant bit up.
+fn decode_packed_indices(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    bytes_per_line: usize,
+    bits_per_pixel: usize,
+    planes: usize,
+) -> Result<Vec<u8>, PcxLoaderError> {
+    if bytes_per_line * 8 < width as usize * bits_per_pixel {
+        return Err(PcxLoaderError::InvalidFormat(format!(
+            "{} bytes per line is too short for {} pixels",
+            bytes_per_line, width
+        )));
+    }
+
+    let line_bytes = bytes_per_line * planes;
+    let total_bytes = line_bytes * height as usize;
+    let decompressed = decode_scanlines(data[2], &data[HDR_BYTES..], total_bytes)?;
+    if decompressed.len() < total_bytes {
+        return Err(PcxLoaderError::InvalidFormat(
+            "Insufficient data".to_string(),
+        ));
+    }
+
+    let mask = (1u8 << bits_per_pixel) - 1;
+    let mut indices = Vec::with_capacity((width * height) as usize);
+    for line in decompressed.chunks_exact(line_bytes).take(height as usize) {
+        for x in 0..width as usize {
+            let bit = x * bits_per_pixel;
+            let shift = 8 - bits_per_pixel - bit % 8;
+            let index = (0..planes).fold(0, |index, plane| {
+                let value = (line[plane * bytes_per_line + bit / 8] >> shift) & mask;
+                index | value << (plane * bits_per_pixel)
+            });
+            indices.push(index);
+        }
+    }
+
+    Ok(indices)
+}
+
+/// Decode the scanlines following the header, stored raw or RLE compressed
+fn decode_scanlines(
+    encoding: u8,
+    data: &[u8],
+    total_bytes: usize,
+) -> Result<Vec<u8>, PcxLoaderError> {
+    match encoding {
+        0 => data
+            .get(..total_bytes)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| PcxLoaderError::InvalidFormat("Insufficient data".to_string())),
+        1 => decompress_rle_data(data, total_bytes),
+        _ => Err(PcxLoaderError::InvalidFormat(format!(
+            "Unsupported encoding {}",
+            encoding
+        ))),
+    }
+}
+
 fn decompress_rle_data(data: &[u8], total_bytes: usize) -> Result<Vec<u8>, PcxLoaderError> {
     let mut decompressed = Vec::new();
     let mut i = 0;
@@ -251,3 +561,155 @@ impl Plugin for PcxLoaderPlugin {
         app.register_asset_loader(PcxLoader);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// PCX header with scanlines padded to 4 bytes
+    fn pcx_header(
+        encoding: u8,
+        bits_per_pixel: u8,
+        planes: u8,
+        width: u16,
+        height: u16,
+    ) -> Vec<u8> {
+        let bytes_per_line = (width * u16::from(bits_per_pixel)).div_ceil(8);
+        let mut data = vec![0u8; 128];
+        data[0] = 0x0A;
+        data[1] = 5;
+        data[2] = encoding;
+        data[3] = bits_per_pixel;
+        data[8..10].copy_from_slice(&(width - 1).to_le_bytes());
+        data[10..12].copy_from_slice(&(height - 1).to_le_bytes());
+        data[65] = planes;
+        data[66..68].copy_from_slice(&bytes_per_line.next_multiple_of(4).to_le_bytes());
+        data
+    }
+
+    /// 8-bit PCX with scanlines padded to 4 bytes and a grey ramp palette
+    fn indexed_pcx(width: u16, height: u16, pixels: &[u8]) -> Vec<u8> {
+        let bytes_per_line = width.next_multiple_of(4);
+        let mut data = pcx_header(1, 8, 1, width, height);
+        for line in pixels.chunks(width as usize) {
+            for x in 0..bytes_per_line as usize {
+                // Run of one, values from 0xC0 up would read as a run length
+                data.extend_from_slice(&[0xC1, line.get(x).copied().unwrap_or(0)]);
+            }
+        }
+        data.push(0x0C);
+        data.extend((0..=255u8).flat_map(|i| [i, i, i]));
+        data
+    }
+
+    #[test]
+    fn palette_indices() {
+        let pixels = [16, 31, 96, 111, 200, 255];
+        let (indices, palette) =
+            parse_pcx_indexed(&indexed_pcx(3, 2, &pixels), &default(), None).unwrap();
+
+        assert_eq!(indices.size(), UVec2::new(3, 2));
+        assert_eq!(indices.data.as_deref(), Some(&pixels[..]));
+        assert_eq!(palette.size(), UVec2::new(256, 1));
+        let palette = palette.data.unwrap();
+        assert_eq!(&palette[96 * 4..97 * 4], &[96, 96, 96, 255]);
+    }
+
+    #[test]
+    fn only_8bit_has_palette_indices() {
+        let pcx = indexed_pcx(2, 2, &[0; 4]);
+        assert!(has_palette_indices(&pcx));
+        assert!(has_palette_indices(&pcx[..HDR_BYTES]));
+        assert!(!has_palette_indices(&pcx[..HDR_BYTES - 1]));
+
+        // 24-bit RGB has 3 planes of 8 bits
+        let mut rgb = pcx.clone();
+        rgb[65] = 3;
+        assert!(!has_palette_indices(&rgb));
+    }
+
+    #[test]
+    fn fullbright_mask() {
+        let pcx = indexed_pcx(3, 1, &[16, 208, 255]);
+        let mask = parse_fullbright(&pcx, &default(), None).unwrap();
+
+        let rgba = mask.data.unwrap();
+        assert_eq!(&rgba[..4], &[0, 0, 0, 255]);
+        assert_eq!(&rgba[4..8], &[208, 208, 208, 255]);
+        assert_eq!(&rgba[8..], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn external_palette() {
+        let pcx = indexed_pcx(3, 1, &[16, 208, 255]);
+        let (pixels, palette) = pcx.split_at(pcx.len() - 768);
+        let pixels = &pixels[..pixels.len() - 1];
+        let settings = PcxLoaderSettings {
+            color_space: PcxColorSpace::Linear,
+            transparent_index: Some(255),
+            ..default()
+        };
+
+        assert!(needs_external_palette(pixels));
+        assert!(!needs_external_palette(&pcx));
+        assert!(!needs_external_palette(&pcx_header(1, 8, 3, 3, 1)));
+        assert!(!needs_external_palette(&pcx_header(0, 1, 4, 3, 1)));
+
+        assert!(parse_pcx(pixels, &settings, None).is_err());
+        let image = parse_pcx(pixels, &settings, Some(palette)).unwrap();
+        assert_eq!(image.texture_descriptor.format, TextureFormat::Rgba8Unorm);
+        assert_eq!(
+            image.data.as_deref(),
+            Some(&[16, 16, 16, 255, 208, 208, 208, 255, 255, 255, 255, 0][..])
+        );
+    }
+
+    #[test]
+    fn low_bit_depths() {
+        let parse = |data: &[u8]| parse_pcx(data, &default(), None).unwrap().data;
+        let reds =
+            |values: &[u8]| -> Vec<u8> { values.iter().flat_map(|&r| [r, 0, 0, 255]).collect() };
+
+        // Uncompressed EGA, pixels 5, 10 and 15 spread over 4 planes of 4 bytes
+        let mut ega = pcx_header(0, 1, 4, 3, 1);
+        for i in 0..16 {
+            ega[16 + i * 3] = i as u8 * 17;
+        }
+        for plane in [0xA0, 0x60, 0xA0, 0x60] {
+            ega.extend_from_slice(&[plane, 0, 0, 0]);
+        }
+        assert_eq!(parse(&ega), Some(reds(&[85, 170, 255])));
+
+        // CGA, pixels 3, 1 and 2 with the first 4 header palette colors
+        let mut cga = pcx_header(1, 2, 1, 3, 1);
+        for i in 0..4 {
+            cga[16 + i * 3] = i as u8 * 85;
+        }
+        // Run of one, the packed pixels would read as a run length
+        cga.extend_from_slice(&[0xC1, 0b1101_1000, 0, 0, 0]);
+        assert_eq!(parse(&cga), Some(reds(&[255, 85, 170])));
+
+        // Monochrome ignores the header palette
+        let mut mono = pcx_header(1, 1, 1, 3, 1);
+        mono.extend_from_slice(&[0b0100_0000, 0, 0, 0]);
+        assert_eq!(
+            parse(&mono),
+            Some(vec![0, 0, 0, 255, 255, 255, 255, 255, 0, 0, 0, 255])
+        );
+    }
+
+    #[test]
+    fn alpha_plane() {
+        // 32-bit RGBA, one plane after another in each scanline
+        let mut pcx = pcx_header(1, 8, 4, 2, 1);
+        for plane in [[10, 20], [30, 40], [50, 60], [0, 128]] {
+            pcx.extend_from_slice(&[plane[0], plane[1], 0, 0]);
+        }
+
+        let image = parse_pcx(&pcx, &default(), None).unwrap();
+        assert_eq!(
+            image.data.as_deref(),
+            Some(&[10, 30, 50, 0, 20, 40, 60, 128][..])
+        );
+    }
+}